@@ -0,0 +1,114 @@
+use crate::color::Color;
+use crate::environment::EnvironmentMap;
+use crate::vec3::Vec3;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// What a ray sees when it does not hit any object
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Background {
+    /// No light from the background, for scenes lit only by emissive objects
+    Black,
+    Solid(Color),
+    /// Vertical gradient from `bottom` to `top`, optionally passing through `middle` at the horizon
+    Gradient {
+        top: Color,
+        bottom: Color,
+        #[serde(default)]
+        middle: Option<Color>,
+    },
+    /// Equirectangular `.hdr` or `.pfm` image surrounding the scene, which also lights it
+    Environment {
+        #[serde(rename = "path")]
+        map: EnvironmentMap,
+        /// Rotation around the vertical axis in degrees
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Gradient {
+            top: Color::new(0.5, 0.7, 1.0),
+            bottom: Color::new(1.0, 1.0, 1.0),
+            middle: None,
+        }
+    }
+}
+
+fn lerp(from: Color, to: Color, t: f64) -> Color {
+    from * (1.0 - t) + to * t
+}
+
+impl Background {
+    pub fn color(&self, direction: &Vec3) -> Color {
+        match self {
+            Background::Black => Color::default(),
+            Background::Solid(color) => *color,
+            Background::Gradient {
+                top,
+                bottom,
+                middle,
+            } => {
+                let t = 0.5 * (Vec3::unit_vector(direction).y() + 1.0);
+
+                match *middle {
+                    Some(middle) if t < 0.5 => lerp(*bottom, middle, 2.0 * t),
+                    Some(middle) => lerp(middle, *top, 2.0 * t - 1.0),
+                    None => lerp(*bottom, *top, t),
+                }
+            }
+            Background::Environment {
+                map,
+                rotation,
+                intensity,
+            } => *intensity * map.color(direction, *rotation),
+        }
+    }
+
+    /// Samples a direction towards the background for direct lighting, if the background supports it.
+    /// Returns the unit direction, the radiance coming from it and its solid angle density
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<(Vec3, Color, f64)> {
+        match self {
+            Background::Environment {
+                map,
+                rotation,
+                intensity,
+            } => map
+                .sample(*rotation, rng)
+                .map(|(direction, radiance, pdf)| (direction, *intensity * radiance, pdf)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_gradient() {
+        let background = Background::default();
+        assert_eq!(background.color(&Vec3::new(0.0, 1.0, 0.0)), Color::new(0.5, 0.7, 1.0));
+        assert_eq!(background.color(&Vec3::new(0.0, -1.0, 0.0)), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_three_stop_gradient() {
+        let background = Background::Gradient {
+            top: Color::new(0.0, 0.0, 1.0),
+            bottom: Color::new(1.0, 0.0, 0.0),
+            middle: Some(Color::new(0.0, 1.0, 0.0)),
+        };
+        assert_eq!(background.color(&Vec3::new(1.0, 0.0, 0.0)), Color::new(0.0, 1.0, 0.0));
+        assert_eq!(background.color(&Vec3::new(0.0, 2.0, 0.0)), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(background.color(&Vec3::new(0.0, -2.0, 0.0)), Color::new(1.0, 0.0, 0.0));
+    }
+}
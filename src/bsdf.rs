@@ -0,0 +1,823 @@
+//! Microfacet scattering models.
+//!
+//! Every BSDF works in a local shading frame where the surface normal is +z, `wo` points back along the
+//! incoming ray and `wi` is the direction light arrives from. For transmissive models +z is the outside
+//! of the object, so `wo` and `wi` can be on either side.
+
+use std::f64::consts::PI;
+
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::sampling::cosine_hemisphere;
+use crate::vec3::Vec3;
+
+const MIN_ALPHA: f64 = 1e-3; // Smoother surfaces are treated as this rough, to keep the distribution finite
+
+/// Scattered direction picked by `Bsdf::sample`
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub wi: Vec3,
+    /// `eval(wo, wi) * |cos(wi)| / pdf`, the factor the light arriving from `wi` is multiplied by
+    pub weight: Color,
+    pub pdf: f64,
+}
+
+pub trait Bsdf {
+    /// Fraction of the light arriving from `wi` that is scattered towards `wo`, per steradian
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color;
+    /// Picks `wi` roughly in proportion to `eval`, `None` if the sampled direction is absorbed
+    fn sample(&self, wo: &Vec3, rng: &mut impl Rng) -> Option<BsdfSample>;
+    /// Density of `sample` picking `wi`, per steradian
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64;
+}
+
+fn reflect(wo: &Vec3, m: &Vec3) -> Vec3 {
+    2.0 * Vec3::dot(wo, m) * *m - *wo
+}
+
+/// Refracts `wo` through the microfacet `m`, on the side of `wo`, with `eta` the ratio of the refractive
+/// index across the surface to the one on the side of `wo`. `None` on total internal reflection
+fn refract(wo: &Vec3, m: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = Vec3::dot(wo, m);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*wo / eta + (cos_i / eta - cos_t) * *m)
+}
+
+/// Unpolarised Fresnel reflectance of a dielectric, `eta` as in `refract`
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Unpolarised Fresnel reflectance of a conductor with the complex refractive index `eta + i k`
+pub fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos2.sqrt() * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rs + rp)
+}
+
+/// Isotropic GGX (Trowbridge-Reitz) distribution of microfacet normals with the Smith masking function
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    /// `roughness` goes from 0.0 (mirror) to 1.0, and is squared to get a perceptually even scale
+    pub fn new(roughness: f64) -> Ggx {
+        Ggx {
+            alpha: (roughness * roughness).clamp(MIN_ALPHA, 1.0),
+        }
+    }
+
+    /// Density of microfacet normals around `m`, projected onto the macro surface
+    pub fn d(&self, m: &Vec3) -> f64 {
+        if m.z() <= 0.0 {
+            return 0.0;
+        }
+
+        let a2 = self.alpha * self.alpha;
+        let t = m.z() * m.z() * (a2 - 1.0) + 1.0;
+
+        a2 / (PI * t * t)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 >= 1.0 {
+            return 0.0;
+        }
+
+        let tan2 = (1.0 - cos2) / cos2;
+        0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0)
+    }
+
+    /// Share of the microfacets visible from `w`
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Share of the microfacets visible from both directions, height correlated
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a microfacet normal visible from `wo` (Heitz 2018), `wo` has to be above the surface
+    pub fn sample_visible(&self, wo: &Vec3, rng: &mut impl Rng) -> Vec3 {
+        // Stretch the view so the distribution becomes a hemisphere
+        let v = Vec3::unit_vector(&Vec3::new(self.alpha * wo.x(), self.alpha * wo.y(), wo.z()));
+
+        let len2 = v.x() * v.x() + v.y() * v.y();
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-v.y(), v.x(), 0.0) / len2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3::cross(&v, &t1);
+
+        // Uniform point on a disk, warped towards the visible half
+        let r = rng.gen::<f64>().sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+
+        Vec3::unit_vector(&Vec3::new(self.alpha * n.x(), self.alpha * n.y(), n.z().max(1e-6)))
+    }
+
+    /// Density of `sample_visible` picking `m`
+    pub fn pdf_visible(&self, wo: &Vec3, m: &Vec3) -> f64 {
+        self.g1(wo) * Vec3::dot(wo, m).max(0.0) * self.d(m) / wo.z()
+    }
+}
+
+/// Complex refractive index of a metal, per RGB channel
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum ComplexIor {
+    Gold,
+    Silver,
+    Copper,
+    Aluminium,
+    Custom { eta: Color, k: Color },
+}
+
+impl ComplexIor {
+    /// Real and imaginary parts, sampled at roughly 650, 550 and 450 nm
+    pub fn eta_k(&self) -> (Color, Color) {
+        match *self {
+            ComplexIor::Gold => (Color::new(0.143, 0.374, 1.442), Color::new(3.983, 2.386, 1.603)),
+            ComplexIor::Silver => (Color::new(0.155, 0.117, 0.138), Color::new(4.828, 3.122, 2.147)),
+            ComplexIor::Copper => (Color::new(0.200, 0.924, 1.102), Color::new(3.912, 2.452, 2.142)),
+            ComplexIor::Aluminium => (Color::new(1.657, 0.880, 0.521), Color::new(9.224, 6.270, 4.837)),
+            ComplexIor::Custom { eta, k } => (eta, k),
+        }
+    }
+}
+
+/// Schlick's approximation of the Fresnel reflectance, from the reflectance at normal incidence
+pub fn schlick(f0: Color, cos_i: f64) -> Color {
+    let w = (1.0 - cos_i.clamp(0.0, 1.0)).powi(5);
+
+    f0 + (Color::new(1.0, 1.0, 1.0) - f0) * w
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Fresnel {
+    /// Exact reflectance of a metal
+    Conductor { eta: Color, k: Color },
+    /// Approximate reflectance, tinted by `f0`
+    Schlick { f0: Color },
+}
+
+impl Fresnel {
+    pub fn eval(&self, cos_i: f64) -> Color {
+        match *self {
+            Fresnel::Conductor { eta, k } => Color::new(
+                fresnel_conductor(cos_i, eta.r(), k.r()),
+                fresnel_conductor(cos_i, eta.g(), k.g()),
+                fresnel_conductor(cos_i, eta.b(), k.b()),
+            ),
+            Fresnel::Schlick { f0 } => schlick(f0, cos_i),
+        }
+    }
+}
+
+/// Rough mirror reflecting from the +z side, used for metals and the coatings of the principled material
+#[derive(Debug, Clone, Copy)]
+pub struct MicrofacetReflection {
+    pub distribution: Ggx,
+    pub fresnel: Fresnel,
+}
+
+impl MicrofacetReflection {
+    pub fn new(fresnel: Fresnel, roughness: f64) -> MicrofacetReflection {
+        MicrofacetReflection {
+            distribution: Ggx::new(roughness),
+            fresnel,
+        }
+    }
+
+    pub fn conductor(ior: &ComplexIor, roughness: f64) -> MicrofacetReflection {
+        let (eta, k) = ior.eta_k();
+
+        MicrofacetReflection::new(Fresnel::Conductor { eta, k }, roughness)
+    }
+}
+
+impl Bsdf for MicrofacetReflection {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color::default();
+        }
+
+        let m = Vec3::unit_vector(&(*wo + *wi));
+        let d = self.distribution.d(&m);
+        let g = self.distribution.g2(wo, wi);
+
+        self.fresnel.eval(Vec3::dot(wo, &m)) * (d * g / (4.0 * wo.z() * wi.z()))
+    }
+
+    fn sample(&self, wo: &Vec3, rng: &mut impl Rng) -> Option<BsdfSample> {
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let m = self.distribution.sample_visible(wo, rng);
+        let wi = reflect(wo, &m);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        let cos_m = Vec3::dot(wo, &m);
+
+        Some(BsdfSample {
+            wi,
+            weight: self.fresnel.eval(cos_m) * (self.distribution.g2(wo, &wi) / self.distribution.g1(wo)),
+            pdf: self.distribution.pdf_visible(wo, &m) / (4.0 * cos_m),
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+
+        let m = Vec3::unit_vector(&(*wo + *wi));
+        self.distribution.pdf_visible(wo, &m) / (4.0 * Vec3::dot(wo, &m))
+    }
+}
+
+/// Rough glass, reflecting and transmitting on both sides of the surface. `ior` is the refractive index
+/// of the inside (-z) relative to the outside
+#[derive(Debug, Clone, Copy)]
+pub struct RoughDielectric {
+    pub distribution: Ggx,
+    pub ior: f64,
+}
+
+impl RoughDielectric {
+    pub fn new(ior: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric {
+            distribution: Ggx::new(roughness),
+            ior,
+        }
+    }
+
+    /// Flips the frame so that `wo` is on the +z side, and gives the relative index across the surface
+    fn orient(&self, wo: &Vec3) -> (f64, f64) {
+        if wo.z() > 0.0 {
+            (1.0, self.ior)
+        } else {
+            (-1.0, 1.0 / self.ior)
+        }
+    }
+
+    /// Microfacet normal (+z side) between `wo` and `wi` in the flipped frame, with whether it's a reflection
+    fn half_vector(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<(Vec3, bool)> {
+        let reflection = wi.z() > 0.0;
+        let m = if reflection { *wo + *wi } else { *wo + eta * *wi };
+        if m.squared_length() == 0.0 {
+            return None;
+        }
+
+        let m = Vec3::unit_vector(&m);
+        let m = if m.z() < 0.0 { -m } else { m };
+
+        // Both directions have to be on the correct side of the microfacet
+        if Vec3::dot(wo, &m) <= 0.0 || (Vec3::dot(wi, &m) > 0.0) != reflection {
+            return None;
+        }
+
+        Some((m, reflection))
+    }
+}
+
+impl Bsdf for RoughDielectric {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let (side, eta) = self.orient(wo);
+        let (wo, wi) = (side * *wo, side * *wi);
+        if wo.z() == 0.0 || wi.z() == 0.0 {
+            return Color::default();
+        }
+        let Some((m, reflection)) = Self::half_vector(&wo, &wi, eta) else {
+            return Color::default();
+        };
+
+        let cos_o = Vec3::dot(&wo, &m);
+        let f = fresnel_dielectric(cos_o, eta);
+        let dg = self.distribution.d(&m) * self.distribution.g2(&wo, &wi);
+
+        let value = if reflection {
+            f * dg / (4.0 * wo.z() * wi.z())
+        } else {
+            let cos_i = Vec3::dot(&wi, &m);
+            let denom = cos_o + eta * cos_i;
+
+            // Radiance gets compressed into a smaller solid angle when it enters a denser medium,
+            // which cancels the eta^2 of the change of variables
+            (1.0 - f) * dg * (cos_i * cos_o).abs() / (wo.z() * wi.z().abs() * denom * denom)
+        };
+
+        Color::new(value, value, value)
+    }
+
+    fn sample(&self, wo: &Vec3, rng: &mut impl Rng) -> Option<BsdfSample> {
+        let (side, eta) = self.orient(wo);
+        let wo = side * *wo;
+        if wo.z() == 0.0 {
+            return None;
+        }
+
+        let m = self.distribution.sample_visible(&wo, rng);
+        let cos_o = Vec3::dot(&wo, &m);
+        let f = fresnel_dielectric(cos_o, eta);
+        let pdf_m = self.distribution.pdf_visible(&wo, &m);
+
+        let (wi, pdf, scale) = if rng.gen::<f64>() < f {
+            let wi = reflect(&wo, &m);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+
+            (wi, f * pdf_m / (4.0 * cos_o), 1.0)
+        } else {
+            let wi = refract(&wo, &m, eta)?;
+            if wi.z() >= 0.0 {
+                return None;
+            }
+
+            let cos_i = Vec3::dot(&wi, &m);
+            let denom = cos_o + eta * cos_i;
+            let jacobian = eta * eta * cos_i.abs() / (denom * denom);
+
+            (wi, (1.0 - f) * pdf_m * jacobian, 1.0 / (eta * eta))
+        };
+
+        let weight = scale * self.distribution.g2(&wo, &wi) / self.distribution.g1(&wo);
+
+        Some(BsdfSample {
+            wi: side * wi,
+            weight: Color::new(weight, weight, weight),
+            pdf,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let (side, eta) = self.orient(wo);
+        let (wo, wi) = (side * *wo, side * *wi);
+        if wo.z() == 0.0 || wi.z() == 0.0 {
+            return 0.0;
+        }
+        let Some((m, reflection)) = Self::half_vector(&wo, &wi, eta) else {
+            return 0.0;
+        };
+
+        let cos_o = Vec3::dot(&wo, &m);
+        let f = fresnel_dielectric(cos_o, eta);
+        let pdf_m = self.distribution.pdf_visible(&wo, &m);
+
+        if reflection {
+            f * pdf_m / (4.0 * cos_o)
+        } else {
+            let cos_i = Vec3::dot(&wi, &m);
+            let denom = cos_o + eta * cos_i;
+
+            (1.0 - f) * pdf_m * eta * eta * cos_i.abs() / (denom * denom)
+        }
+    }
+}
+
+fn luminance(c: Color) -> f64 {
+    0.2126 * c.r() + 0.7152 * c.g() + 0.0722 * c.b()
+}
+
+/// Layered material in the style of the Disney principled BSDF: a clear coat on top of a blend of metal,
+/// glass and a diffuse base with a specular layer. Parameters are mostly 0.0-1.0
+#[derive(Debug, Clone, Copy)]
+pub struct Principled {
+    pub base_color: Color,
+    pub metallic: f64,
+    pub roughness: f64,
+    /// Reflectance of the dielectric layer, 0.5 is the 4% of common materials
+    pub specular: f64,
+    pub transmission: f64,
+    pub ior: f64,
+    pub clearcoat: f64,
+    pub clearcoat_roughness: f64,
+    /// Soft white rim on cloth-like surfaces
+    pub sheen: f64,
+}
+
+/// Scattering components of the principled material, in the order they are picked by `Principled::sample`
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const METAL: usize = 2;
+const GLASS: usize = 3;
+const CLEARCOAT: usize = 4;
+
+impl Principled {
+    fn dielectric_f0(&self) -> Color {
+        let f0 = 0.08 * self.specular;
+        Color::new(f0, f0, f0)
+    }
+
+    fn specular_lobe(&self) -> MicrofacetReflection {
+        MicrofacetReflection::new(Fresnel::Schlick { f0: self.dielectric_f0() }, self.roughness)
+    }
+
+    fn metal_lobe(&self) -> MicrofacetReflection {
+        MicrofacetReflection::new(Fresnel::Schlick { f0: self.base_color }, self.roughness)
+    }
+
+    fn glass_lobe(&self) -> RoughDielectric {
+        RoughDielectric::new(self.ior, self.roughness)
+    }
+
+    fn clearcoat_lobe(&self) -> MicrofacetReflection {
+        let f0 = Color::new(0.04, 0.04, 0.04);
+        MicrofacetReflection::new(Fresnel::Schlick { f0 }, self.clearcoat_roughness)
+    }
+
+    /// Light that gets through the clear coat, seen from `wo`
+    fn clearcoat_transmittance(&self, wo: &Vec3) -> f64 {
+        if wo.z() <= 0.0 {
+            return 1.0;
+        }
+
+        1.0 - self.clearcoat * schlick(Color::new(0.04, 0.04, 0.04), wo.z()).r()
+    }
+
+    /// Chance of picking each component, roughly in proportion to how much light it scatters
+    fn lobe_probabilities(&self, wo: &Vec3) -> [f64; 5] {
+        let mut weights = [0.0; 5];
+
+        // Only glass scatters light that arrives from inside
+        if wo.z() > 0.0 {
+            let opaque = (1.0 - self.metallic) * (1.0 - self.transmission);
+            let coated = self.clearcoat_transmittance(wo);
+
+            weights[DIFFUSE] = coated * opaque * (luminance(self.base_color) + self.sheen);
+            weights[SPECULAR] = coated * opaque * luminance(schlick(self.dielectric_f0(), wo.z()));
+            weights[METAL] = coated * self.metallic;
+            weights[CLEARCOAT] = self.clearcoat * schlick(Color::new(0.04, 0.04, 0.04), wo.z()).r();
+        }
+        weights[GLASS] = (1.0 - self.metallic) * self.transmission;
+
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            weights.iter_mut().for_each(|w| *w /= total);
+        }
+
+        weights
+    }
+}
+
+impl Bsdf for Principled {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let opaque = (1.0 - self.metallic) * (1.0 - self.transmission);
+        let mut f = Color::default();
+
+        if wo.z() > 0.0 && wi.z() > 0.0 {
+            let cos_d = Vec3::dot(wi, &Vec3::unit_vector(&(*wo + *wi)));
+            let diffuse = self.base_color * (1.0 - 0.08 * self.specular) / PI;
+            let sheen = self.sheen * (1.0 - cos_d).powi(5) * white;
+
+            f = opaque * (diffuse + sheen + self.specular_lobe().eval(wo, wi))
+                + self.metallic * self.metal_lobe().eval(wo, wi);
+        }
+
+        if self.transmission > 0.0 {
+            let glass = self.glass_lobe().eval(wo, wi);
+            // The base colour tints the light going through
+            let tint = if wo.z() * wi.z() < 0.0 { self.base_color } else { white };
+            f += (1.0 - self.metallic) * self.transmission * glass * tint;
+        }
+
+        f = self.clearcoat_transmittance(wo) * f;
+        if self.clearcoat > 0.0 {
+            f += self.clearcoat * self.clearcoat_lobe().eval(wo, wi);
+        }
+
+        f
+    }
+
+    fn sample(&self, wo: &Vec3, rng: &mut impl Rng) -> Option<BsdfSample> {
+        let probabilities = self.lobe_probabilities(wo);
+
+        let mut u = rng.gen::<f64>();
+        let mut lobe = 0;
+        while lobe < probabilities.len() - 1 && u >= probabilities[lobe] {
+            u -= probabilities[lobe];
+            lobe += 1;
+        }
+        if probabilities[lobe] == 0.0 {
+            return None;
+        }
+
+        let wi = match lobe {
+            DIFFUSE => cosine_hemisphere(rng),
+            SPECULAR => self.specular_lobe().sample(wo, rng)?.wi,
+            METAL => self.metal_lobe().sample(wo, rng)?.wi,
+            GLASS => self.glass_lobe().sample(wo, rng)?.wi,
+            _ => self.clearcoat_lobe().sample(wo, rng)?.wi,
+        };
+
+        // The other components could have picked the same direction, so they all count towards the pdf
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample {
+            wi,
+            weight: self.eval(wo, &wi) * (wi.z().abs() / pdf),
+            pdf,
+        })
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let p = self.lobe_probabilities(wo);
+        let mut pdf = 0.0;
+
+        if p[DIFFUSE] > 0.0 && wi.z() > 0.0 {
+            pdf += p[DIFFUSE] * wi.z() / PI;
+        }
+        if p[SPECULAR] > 0.0 {
+            pdf += p[SPECULAR] * self.specular_lobe().pdf(wo, wi);
+        }
+        if p[METAL] > 0.0 {
+            pdf += p[METAL] * self.metal_lobe().pdf(wo, wi);
+        }
+        if p[GLASS] > 0.0 {
+            pdf += p[GLASS] * self.glass_lobe().pdf(wo, wi);
+        }
+        if p[CLEARCOAT] > 0.0 {
+            pdf += p[CLEARCOAT] * self.clearcoat_lobe().pdf(wo, wi);
+        }
+
+        pdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::random_unit_vector;
+    use assert_approx_eq::assert_approx_eq;
+    use rand::rngs::StdRng;
+
+    fn directions() -> Vec<Vec3> {
+        vec![
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::unit_vector(&Vec3::new(0.3, -0.2, 0.9)),
+            Vec3::unit_vector(&Vec3::new(-0.8, 0.4, 0.2)),
+            Vec3::unit_vector(&Vec3::new(0.5, 0.5, -0.7)),
+        ]
+    }
+
+    fn max_channel(c: Color) -> f64 {
+        c.r().max(c.g()).max(c.b())
+    }
+
+    /// Checks that `sample` agrees with `eval` and `pdf`, and that `pdf` integrates to the share of
+    /// successful samples
+    fn check_consistency(bsdf: &impl Bsdf, wo: &Vec3) {
+        const N: usize = 40_000;
+        let mut rng = StdRng::seed_from_u64(3);
+
+        let mut sampled = 0;
+        for _ in 0..N {
+            let Some(s) = bsdf.sample(wo, &mut rng) else {
+                continue;
+            };
+            sampled += 1;
+
+            let pdf = bsdf.pdf(wo, &s.wi);
+            assert!((pdf - s.pdf).abs() <= 1e-6 * pdf.max(1.0), "pdf {pdf} != {} for {wo:?}", s.pdf);
+            let expected = bsdf.eval(wo, &s.wi) * (s.wi.z().abs() / s.pdf);
+            assert!((expected - s.weight).length() < 1e-6 * max_channel(expected).max(1.0));
+        }
+
+        // Half of the directions uniform over the sphere and half from the BSDF itself, which keeps the
+        // estimate steady for narrow lobes. Absorbed BSDF samples count as zero
+        let integral = (0..N)
+            .filter_map(|i| {
+                if i % 2 == 0 {
+                    bsdf.sample(wo, &mut rng).map(|s| s.wi)
+                } else {
+                    Some(random_unit_vector(&mut rng))
+                }
+            })
+            .map(|wi| {
+                let pdf = bsdf.pdf(wo, &wi);
+                pdf / (0.5 / (4.0 * PI) + 0.5 * pdf)
+            })
+            .sum::<f64>()
+            / N as f64;
+        let share = sampled as f64 / N as f64;
+        assert!((integral - share).abs() < 0.03, "pdf integrates to {integral}, {share} sampled for {wo:?}");
+    }
+
+    #[test]
+    fn test_fresnel_dielectric() {
+        assert_approx_eq!(fresnel_dielectric(1.0, 1.5), 0.04);
+        assert_approx_eq!(fresnel_dielectric(0.0, 1.5), 1.0);
+        // Total internal reflection past the critical angle
+        assert_eq!(fresnel_dielectric(0.5, 1.0 / 1.5), 1.0);
+        assert!(fresnel_dielectric(0.9, 1.0 / 1.5) < 1.0);
+    }
+
+    #[test]
+    fn test_fresnel_conductor() {
+        // Without absorption a conductor is a dielectric
+        for cos in [0.1, 0.5, 0.9, 1.0] {
+            assert_approx_eq!(fresnel_conductor(cos, 1.5, 0.0), fresnel_dielectric(cos, 1.5));
+        }
+
+        // Normal incidence
+        let (eta, k) = (0.2, 3.9);
+        let expected = ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+        assert_approx_eq!(fresnel_conductor(1.0, eta, k), expected);
+        assert_approx_eq!(fresnel_conductor(0.0, eta, k), 1.0);
+    }
+
+    #[test]
+    fn test_presets() {
+        let gold = MicrofacetReflection::conductor(&ComplexIor::Gold, 0.0);
+        let reflectance = gold.fresnel.eval(1.0);
+
+        // Gold reflects red much more than blue
+        assert!(reflectance.r() > 0.9 && reflectance.b() < 0.5);
+    }
+
+    #[test]
+    fn test_ggx_normalised() {
+        // The projected area of the microfacets equals the macro surface
+        let ggx = Ggx::new(0.6);
+        let mut rng = StdRng::seed_from_u64(5);
+        let n = 200_000;
+        let integral = (0..n)
+            .map(|_| {
+                let m = random_unit_vector(&mut rng);
+                ggx.d(&m) * m.z().max(0.0)
+            })
+            .sum::<f64>()
+            * 4.0
+            * PI
+            / n as f64;
+
+        assert!((integral - 1.0).abs() < 0.02, "{integral}");
+    }
+
+    #[test]
+    fn test_conductor_consistency() {
+        for roughness in [0.2, 0.7] {
+            let bsdf = MicrofacetReflection::conductor(&ComplexIor::Copper, roughness);
+            for wo in directions().iter().filter(|w| w.z() > 0.0) {
+                check_consistency(&bsdf, wo);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rough_dielectric_consistency() {
+        for roughness in [0.3, 0.8] {
+            let bsdf = RoughDielectric::new(1.5, roughness);
+            for wo in directions() {
+                check_consistency(&bsdf, &wo);
+            }
+        }
+    }
+
+    #[test]
+    fn test_conductor_energy() {
+        // A perfect reflector can only lose energy to masking
+        let mirror = ComplexIor::Custom {
+            eta: Color::new(0.0, 0.0, 0.0),
+            k: Color::new(1e6, 1e6, 1e6),
+        };
+        let bsdf = MicrofacetReflection::conductor(&mirror, 0.5);
+        let mut rng = StdRng::seed_from_u64(9);
+        let wo = Vec3::unit_vector(&Vec3::new(0.4, 0.0, 0.8));
+
+        let n = 50_000;
+        let albedo = (0..n)
+            .filter_map(|_| bsdf.sample(&wo, &mut rng))
+            .map(|s| s.weight.r())
+            .sum::<f64>()
+            / n as f64;
+
+        assert!(albedo > 0.85 && albedo <= 1.0, "{albedo}");
+    }
+
+    #[test]
+    fn test_smooth_dielectric_refracts() {
+        let bsdf = RoughDielectric::new(1.5, 0.0);
+        let mut rng = StdRng::seed_from_u64(1);
+        let wo = Vec3::unit_vector(&Vec3::new(0.5, 0.0, 1.0));
+
+        // Snell's law for the transmitted directions
+        let sin_o = wo.x();
+        for s in (0..100).filter_map(|_| bsdf.sample(&wo, &mut rng)) {
+            if s.wi.z() < 0.0 {
+                assert!((-s.wi.x() - sin_o / 1.5).abs() < 0.01, "{:?}", s.wi);
+            } else {
+                assert!((s.wi.x() + sin_o).abs() < 0.01, "{:?}", s.wi);
+            }
+        }
+    }
+
+    #[test]
+    fn test_principled_consistency() {
+        let plastic = Principled {
+            base_color: Color::new(0.8, 0.2, 0.1),
+            metallic: 0.0,
+            roughness: 0.4,
+            specular: 0.5,
+            transmission: 0.0,
+            ior: 1.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.5,
+        };
+        let coated_metal = Principled {
+            metallic: 0.7,
+            clearcoat: 1.0,
+            clearcoat_roughness: 0.2,
+            ..plastic
+        };
+        let glass = Principled {
+            transmission: 0.9,
+            roughness: 0.3,
+            ..plastic
+        };
+
+        for bsdf in [plastic, coated_metal] {
+            for wo in directions().iter().filter(|w| w.z() > 0.0) {
+                check_consistency(&bsdf, wo);
+            }
+        }
+        for wo in directions() {
+            check_consistency(&glass, &wo);
+        }
+    }
+
+    #[test]
+    fn test_principled_energy() {
+        // A white, smooth dielectric base scatters at most the light that arrives
+        let white = Principled {
+            base_color: Color::new(1.0, 1.0, 1.0),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            transmission: 0.0,
+            ior: 1.5,
+            clearcoat: 1.0,
+            clearcoat_roughness: 0.1,
+            sheen: 0.0,
+        };
+        let mut rng = StdRng::seed_from_u64(2);
+        let wo = Vec3::unit_vector(&Vec3::new(0.2, 0.1, 0.9));
+
+        let n = 50_000;
+        let albedo = (0..n)
+            .filter_map(|_| white.sample(&wo, &mut rng))
+            .map(|s| s.weight.g())
+            .sum::<f64>()
+            / n as f64;
+
+        assert!(albedo > 0.8 && albedo < 1.02, "{albedo}");
+    }
+}
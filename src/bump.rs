@@ -0,0 +1,197 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::hit::HitRecord;
+use crate::texture::{Images, LoadError, Texture};
+use crate::vec3::Vec3;
+
+/// Step in u and v used to measure the slope of height textures
+const DELTA: f64 = 1e-4;
+
+/// Surface detail that tilts the shading normal, without changing the shape of the object
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Bump {
+    /// Bumps raised by the brightness of a texture, `strength` is the height of white in world units
+    Height {
+        #[serde(deserialize_with = "linear")]
+        texture: Texture,
+        #[serde(default = "default_strength")]
+        strength: f64,
+    },
+    /// Tangent space normal map, where red, green and blue hold the normal along u, v and out of the
+    /// surface, mapped from -1.0..1.0 to 0.0..1.0. `strength` scales the tilt
+    Normal {
+        #[serde(deserialize_with = "linear")]
+        texture: Texture,
+        #[serde(default = "default_strength")]
+        strength: f64,
+    },
+}
+
+fn default_strength() -> f64 {
+    1.0
+}
+
+/// Heights and normals are stored as they are, so images are linear unless `srgb` says otherwise
+fn linear<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Texture, D::Error> {
+    Texture::deserialize(deserializer).map(Texture::linear)
+}
+
+impl Bump {
+    /// Loads the images of the texture, sharing the ones in `images`
+    pub fn load(&mut self, images: &Images) -> Result<(), LoadError> {
+        match self {
+            Bump::Height { texture, .. } | Bump::Normal { texture, .. } => texture.load(images),
+        }
+    }
+
+    /// Tilted unit shading normal of the hit, on the same side of the surface as the original
+    pub fn normal(&self, rec: &HitRecord) -> Vec3 {
+        let n = Vec3::unit_vector(&rec.normal);
+
+        let bumped = match self {
+            Bump::Height { texture, strength } => {
+                // Height at the hit moved along the surface, the rest of the hit stays as it is
+                let height = |du: f64, dv: f64| {
+                    let shifted = HitRecord {
+                        u: rec.u + du,
+                        v: rec.v + dv,
+                        point: rec.point + du * rec.dpdu + dv * rec.dpdv,
+                        ..*rec
+                    };
+                    let c = texture.value_at(&shifted);
+                    strength * (c.r() + c.g() + c.b()) / 3.0
+                };
+                let h = height(0.0, 0.0);
+                let dhdu = (height(DELTA, 0.0) - h) / DELTA;
+                let dhdv = (height(0.0, DELTA) - h) / DELTA;
+
+                // Tangents of the surface pushed out along the normal by the height. The tangents of some
+                // faces turn the other way around the normal, but the height always pushes outwards
+                let bumped = Vec3::cross(&(rec.dpdu + dhdu * n), &(rec.dpdv + dhdv * n));
+                if Vec3::dot(&bumped, &n) < 0.0 {
+                    -bumped
+                } else {
+                    bumped
+                }
+            }
+            Bump::Normal { texture, strength } => {
+                let c = texture.value_at(rec);
+                let (t, b, n) = rec.shading_frame();
+
+                strength * (2.0 * c.r() - 1.0) * t + strength * (2.0 * c.g() - 1.0) * b + (2.0 * c.b() - 1.0) * n
+            }
+        };
+
+        // Surfaces without tangents, or maps pointing into the surface, keep their normal
+        if Vec3::dot(&bumped, &n) > 0.0 && bumped.length().is_finite() {
+            Vec3::unit_vector(&bumped)
+        } else {
+            n
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::hit::test_record;
+    use crate::material::Material;
+
+    /// Hit on the floor y = 0, with u along +x and v along -z
+    fn floor(material: &Material, point: Vec3) -> HitRecord<'_> {
+        let tangents = (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        test_record(material, point, Vec3::new(0.0, 1.0, 0.0), tangents)
+    }
+
+    fn parse(bump: &str) -> Bump {
+        ron::from_str(bump).unwrap()
+    }
+
+    #[test]
+    fn test_height() {
+        let material = Material::default();
+        let rec = floor(&material, Vec3::new(0.3, 0.0, -0.2));
+
+        // Flat textures change nothing
+        let flat = parse("Height(texture: Vec3(0.5, 0.5, 0.5))");
+        assert!((flat.normal(&rec) - rec.normal).length() < 1e-9);
+
+        // Rising by 1 per unit along both u and v tilts the normal back against both, towards -x and +z
+        let ramp = parse("Height(texture: Uv, strength: 3.0)");
+        let n = ramp.normal(&rec);
+        let expected = Vec3::unit_vector(&Vec3::new(-1.0, 1.0, 1.0));
+        assert!((n - expected).length() < 1e-6, "{n:?}");
+
+        // Solid textures are measured along the surface too
+        let solid = parse("Height(texture: Noise(scale: 4.0), strength: 0.1)");
+        let n = solid.normal(&rec);
+        assert!((n.length() - 1.0).abs() < 1e-9 && n.y() > 0.0);
+        assert!(n.x().abs() > 1e-3 || n.z().abs() > 1e-3);
+    }
+
+    #[test]
+    fn test_height_vertex_colors() {
+        let material = Material::default();
+        let rec = floor(&material, Vec3::new(0.3, 0.0, -0.2));
+        let ramp = parse("Height(texture: Multiply(VertexColor, Uv), strength: 3.0)");
+
+        // Heights come from the whole hit, so black corners flatten the ramp
+        let black = HitRecord {
+            color: Some(Color::default()),
+            ..rec
+        };
+        assert!((ramp.normal(&black) - rec.normal).length() < 1e-9);
+        assert!(ramp.normal(&rec).x() < -0.1);
+    }
+
+    #[test]
+    fn test_height_flipped_tangents() {
+        let material = Material::default();
+        let rec = HitRecord {
+            dpdv: Vec3::new(0.0, 0.0, 1.0),
+            ..floor(&material, Vec3::new(0.3, 0.0, -0.2))
+        };
+
+        // Bumps stay on the outside even when u and v turn the other way around the normal
+        let n = parse("Height(texture: Uv, strength: 3.0)").normal(&rec);
+        assert!(n.y() > 0.0 && n.x() < 0.0);
+    }
+
+    #[test]
+    fn test_normal_map() {
+        let material = Material::default();
+        let rec = floor(&material, Vec3::default());
+
+        // The flat colour of normal maps points straight out
+        let flat = parse("Normal(texture: Vec3(0.5, 0.5, 1.0))");
+        assert!((flat.normal(&rec) - rec.normal).length() < 1e-9);
+
+        // Green tilts towards v, which is -z here
+        let tilted = parse("Normal(texture: Vec3(0.5, 1.0, 1.0))");
+        let expected = Vec3::unit_vector(&Vec3::new(0.0, 1.0, -1.0));
+        assert!((tilted.normal(&rec) - expected).length() < 1e-9);
+
+        let halved = parse("Normal(texture: Vec3(0.5, 1.0, 1.0), strength: 0.5)");
+        let expected = Vec3::unit_vector(&Vec3::new(0.0, 1.0, -0.5));
+        assert!((halved.normal(&rec) - expected).length() < 1e-9);
+
+        // Normals pointing into the surface are ignored
+        let inside = parse("Normal(texture: Vec3(0.5, 0.5, 0.0))");
+        assert_eq!(inside.normal(&rec), rec.normal);
+    }
+
+    #[test]
+    fn test_normal_map_file() {
+        // 8-bit files hold the flat normal as (128, 128, 255), which must not be sRGB decoded
+        let path = std::env::temp_dir().join(format!("rt_bump_{}.ppm", std::process::id()));
+        std::fs::write(&path, b"P3 1 1 255\n128 128 255\n").unwrap();
+        let mut flat = parse(&format!("Normal(texture: Image(path: {path:?}))"));
+        flat.load(&Images::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let material = Material::default();
+        let rec = floor(&material, Vec3::default());
+        assert!((flat.normal(&rec) - rec.normal).length() < 0.01);
+    }
+}
@@ -0,0 +1,278 @@
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+const BINS: usize = 12; // Amount of buckets the centroids are sorted into when looking for a split
+const MAX_LEAF_SIZE: usize = 4; // Leaves with more objects than this are always split
+const MAX_DEPTH: usize = 60; // Keeps the traversal stack bounded in degenerate scenes
+const TRAVERSAL_COST: f64 = 0.125; // Cost of visiting a node relative to intersecting an object
+
+#[derive(Debug)]
+enum Node {
+    Leaf {
+        bounds: Aabb,
+        first: usize,
+        count: usize,
+    },
+    // The first child always directly follows its parent in the node list
+    Interior {
+        bounds: Aabb,
+        second: usize,
+        axis: usize,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+struct Primitive {
+    index: usize,
+    bounds: Aabb,
+    centroid: Vec3,
+}
+
+/// Bounding volume hierarchy built with the surface area heuristic
+#[derive(Debug)]
+pub struct Bvh {
+    objects: Vec<Box<dyn Hittable>>,
+    nodes: Vec<Node>,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Box<dyn Hittable>>) -> Bvh {
+        let mut primitives: Vec<Primitive> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| {
+                let bounds = object.bounding_box();
+                Primitive {
+                    index,
+                    bounds,
+                    centroid: bounds.centroid(),
+                }
+            })
+            .collect();
+
+        let mut nodes = Vec::with_capacity(2 * objects.len());
+        build(&mut nodes, &mut primitives, 0, 0);
+
+        // Reorder the objects so that every leaf points to a contiguous range
+        let mut slots: Vec<Option<Box<dyn Hittable>>> = objects.into_iter().map(Some).collect();
+        let objects = primitives
+            .iter()
+            .map(|p| slots[p.index].take().expect("Object used twice in BVH"))
+            .collect();
+
+        Bvh { objects, nodes }
+    }
+}
+
+fn build(nodes: &mut Vec<Node>, primitives: &mut [Primitive], first: usize, depth: usize) {
+    let bounds = primitives
+        .iter()
+        .fold(Aabb::empty(), |b, p| b.union(&p.bounds));
+    let leaf = Node::Leaf {
+        bounds,
+        first,
+        count: primitives.len(),
+    };
+
+    if primitives.len() <= 1 || depth >= MAX_DEPTH {
+        nodes.push(leaf);
+        return;
+    }
+
+    let centroid_bounds = primitives
+        .iter()
+        .fold(Aabb::empty(), |b, p| b.grow(&p.centroid));
+    let axis = centroid_bounds.longest_axis();
+    let extent = centroid_bounds.max[axis] - centroid_bounds.min[axis];
+
+    if extent <= 0.0 {
+        nodes.push(leaf);
+        return;
+    }
+
+    let bin_of = |p: &Primitive| {
+        let offset = (p.centroid[axis] - centroid_bounds.min[axis]) / extent;
+        ((offset * BINS as f64) as usize).min(BINS - 1)
+    };
+
+    let mut bins = [(Aabb::empty(), 0usize); BINS];
+    for p in primitives.iter() {
+        let bin = &mut bins[bin_of(p)];
+        bin.0 = bin.0.union(&p.bounds);
+        bin.1 += 1;
+    }
+
+    // Cost of splitting after each bin, relative to the cost of intersecting every object
+    let (split, cost) = (0..BINS - 1)
+        .map(|split| {
+            let (left, left_count) = bins[..=split]
+                .iter()
+                .fold((Aabb::empty(), 0), |(b, n), bin| (b.union(&bin.0), n + bin.1));
+            let (right, right_count) = bins[split + 1..]
+                .iter()
+                .fold((Aabb::empty(), 0), |(b, n), bin| (b.union(&bin.0), n + bin.1));
+
+            let cost = TRAVERSAL_COST
+                + (left_count as f64 * left.surface_area()
+                    + right_count as f64 * right.surface_area())
+                    / bounds.surface_area().max(f64::EPSILON);
+
+            (split, cost)
+        })
+        .fold((0, f64::INFINITY), |best, candidate| {
+            if candidate.1 < best.1 {
+                candidate
+            } else {
+                best
+            }
+        });
+
+    if primitives.len() <= MAX_LEAF_SIZE && cost >= primitives.len() as f64 {
+        nodes.push(leaf);
+        return;
+    }
+
+    let mut mid = 0;
+    for i in 0..primitives.len() {
+        if bin_of(&primitives[i]) <= split {
+            primitives.swap(i, mid);
+            mid += 1;
+        }
+    }
+
+    if mid == 0 || mid == primitives.len() {
+        mid = primitives.len() / 2;
+        primitives.select_nth_unstable_by(mid, |a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+    }
+
+    let node = nodes.len();
+    nodes.push(leaf);
+
+    let (left, right) = primitives.split_at_mut(mid);
+    build(nodes, left, first, depth + 1);
+    let second = nodes.len();
+    build(nodes, right, first + mid, depth + 1);
+
+    nodes[node] = Node::Interior {
+        bounds,
+        second,
+        axis,
+    };
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut hit_record = None;
+        let mut closest_so_far = t_max;
+
+        let mut stack = [0usize; MAX_DEPTH + 2];
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let index = stack[stack_len];
+            let node = &self.nodes[index];
+
+            if !node.bounds().hit(r, t_min, closest_so_far) {
+                continue;
+            }
+
+            match *node {
+                Node::Leaf { first, count, .. } => {
+                    for object in &self.objects[first..first + count] {
+                        if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+                            closest_so_far = rec.t;
+                            hit_record = Some(rec);
+                        }
+                    }
+                }
+                Node::Interior { second, axis, .. } => {
+                    // Visit the child closer to the ray origin first
+                    let (near, far) = if r.direction[axis] < 0.0 {
+                        (second, index + 1)
+                    } else {
+                        (index + 1, second)
+                    };
+
+                    stack[stack_len] = far;
+                    stack[stack_len + 1] = near;
+                    stack_len += 2;
+                }
+            }
+        }
+
+        hit_record
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes
+            .first()
+            .map_or_else(Aabb::empty, |node| *node.bounds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::HittableList;
+    use crate::material::Material;
+    use crate::sphere::Sphere;
+    use rand::prelude::*;
+
+    fn spheres(rng: &mut StdRng) -> Vec<Box<dyn Hittable>> {
+        (0..200)
+            .map(|_| {
+                let center = Vec3::new(
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                );
+                Box::new(Sphere::new(center, rng.gen_range(0.1..1.0), Material::default()))
+                    as Box<dyn Hittable>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bvh_matches_list() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let bvh = Bvh::new(spheres(&mut StdRng::seed_from_u64(1)));
+        let list = HittableList::new(spheres(&mut StdRng::seed_from_u64(1)));
+
+        for _ in 0..1000 {
+            let origin = Vec3::new(
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-15.0..15.0),
+            );
+            let direction = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            let ray = Ray::new(origin, direction);
+
+            let expected = list.hit(&ray, 0.001, f64::MAX).map(|rec| rec.t);
+            let actual = bvh.hit(&ray, 0.001, f64::MAX).map(|rec| rec.t);
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_bvh_bounds() {
+        let bvh = Bvh::new(spheres(&mut StdRng::seed_from_u64(1)));
+        let list = HittableList::new(spheres(&mut StdRng::seed_from_u64(1)));
+
+        assert_eq!(bvh.bounding_box(), list.bounding_box());
+        assert!(Bvh::new(Vec::new()).hit(&Ray::default(), 0.0, f64::MAX).is_none());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::ray::Ray;
+use crate::sampling::random_in_unit_disk;
 use crate::vec3::Vec3;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -55,8 +56,8 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    pub fn get_ray(&self, s: f64, t: f64, rng: &mut impl Rng) -> Ray {
+        let rd = self.lens_radius * random_in_unit_disk(rng);
         let offset = self.u * rd.x() + self.v * rd.y();
         Ray {
             origin: self.origin + offset,
@@ -66,14 +67,3 @@ impl Camera {
         }
     }
 }
-
-pub fn random_in_unit_disk() -> Vec3 {
-    let mut rng = rand::thread_rng();
-
-    loop {
-        let p = 2.0 * Vec3::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>());
-        if Vec3::dot(&p, &p) < 1.0 {
-            return p;
-        }
-    }
-}
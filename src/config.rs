@@ -1,19 +1,129 @@
 use crate::{
+    background::Background,
+    bvh::Bvh,
     camera::Camera,
+    color::Color,
+    csg::{Csg, Operation},
     cube::Cube,
+    gltf::GltfFile,
     hit::{Hittable, HittableList},
+    instance::Instance,
     material::Material,
+    mesh::{Mesh, MeshData},
+    obj::ObjFile,
+    ply::PlyFile,
     plane_surf::Plane,
     sphere::Sphere,
+    stl::StlFile,
+    texture::{self, Images},
+    tone_map::ToneMap,
+    transform::{Mat4, Transform},
+    triangle::{Triangle, DEFAULT_UVS},
     vec3::Vec3,
     cylinder::Cylinder,
 };
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum Error {
+    MissingCamera,
+    UnknownPrototype(String),
+    RecursivePrototype(String),
+    NotSolid,
+    InvalidTexture(texture::LoadError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::MissingCamera => write!(f, "no camera given, and none taken from a model"),
+            Error::UnknownPrototype(name) => write!(f, "no prototype named {name:?}"),
+            Error::RecursivePrototype(name) => write!(f, "prototype {name:?} uses itself"),
+            Error::NotSolid => write!(f, "Csg can only combine spheres, cubes, cylinders and their instances and combinations"),
+            Error::InvalidTexture(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Named objects of the config, each processed once the first time it's used
+pub struct Prototypes<'a> {
+    definitions: &'a HashMap<String, Box<dyn UnprocessedData>>,
+    processed: RefCell<HashMap<String, Arc<dyn Hittable>>>,
+    /// Prototypes being processed, to catch the ones that use themselves
+    pending: RefCell<Vec<String>>,
+    images: Images,
+}
+
+impl<'a> Prototypes<'a> {
+    pub fn new(definitions: &'a HashMap<String, Box<dyn UnprocessedData>>) -> Self {
+        Prototypes {
+            definitions,
+            processed: RefCell::default(),
+            pending: RefCell::default(),
+            images: Images::default(),
+        }
+    }
+
+    /// Copy of `material` with the images of its textures loaded, shared by every entry of the config
+    pub fn material(&self, material: &Material) -> Result<Material, Error> {
+        let mut material = material.clone();
+        material.load(&self.images).map_err(Error::InvalidTexture)?;
+        Ok(material)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn Hittable>, Error> {
+        if let Some(object) = self.processed.borrow().get(name) {
+            return Ok(object.clone());
+        }
+        let definition = self
+            .definitions
+            .get(name)
+            .ok_or_else(|| Error::UnknownPrototype(name.to_string()))?;
+        if self.pending.borrow().iter().any(|pending| pending == name) {
+            return Err(Error::RecursivePrototype(name.to_string()));
+        }
+
+        self.pending.borrow_mut().push(name.to_string());
+        let object: Arc<dyn Hittable> = Arc::from(definition.process(self)?);
+        self.pending.borrow_mut().pop();
+        self.processed.borrow_mut().insert(name.to_string(), object.clone());
+
+        Ok(object)
+    }
+}
+
+/// Copies of `object`, one placed by each transform
+fn instances(object: Arc<dyn Hittable>, transforms: &[Transform]) -> Box<dyn Hittable> {
+    let mut instances: Vec<Box<dyn Hittable>> = transforms
+        .iter()
+        .filter_map(|transform| {
+            let instance = Instance::new(object.clone(), transform.matrix());
+            if instance.is_none() {
+                eprintln!("Skipping an instance scaled to nothing: {transform:?}");
+            }
+            instance.map(|instance| Box::new(instance) as Box<dyn Hittable>)
+        })
+        .collect();
+
+    // A single copy is left as it is, so that it can still be used in a `Csg`
+    if instances.len() == 1 {
+        return instances.remove(0);
+    }
+    Box::new(Bvh::new(instances))
+}
 
 #[typetag::serde]
 pub trait UnprocessedData: Debug {
-    fn process(&self) -> Box<dyn Hittable>;
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error>;
+
+    /// Camera brought along by the entry, which replaces the one of the config
+    fn camera(&self) -> Option<UnprocessedCamera> {
+        None
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,8 +135,8 @@ pub struct UnprocessedCube {
 
 #[typetag::serde(name = "Cube")]
 impl UnprocessedData for UnprocessedCube {
-    fn process(&self) -> Box<dyn Hittable> {
-        Box::new(Cube::new(self.p0, self.p1, self.mat))
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        Ok(Box::new(Cube::new(self.p0, self.p1, prototypes.material(&self.mat)?)))
     }
 }
 
@@ -41,14 +151,14 @@ pub struct UnprocessedPlane {
 
 #[typetag::serde(name = "Plane")]
 impl UnprocessedData for UnprocessedPlane {
-    fn process(&self) -> Box<dyn Hittable> {
-        Box::new(Plane::new(
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        Ok(Box::new(Plane::new(
             self.normal,
             self.dist,
             self.width,
             self.height,
-            self.material,
-        ))
+            prototypes.material(&self.material)?,
+        )))
     }
 }
 
@@ -61,8 +171,8 @@ pub struct UnprocessedSphere {
 
 #[typetag::serde(name = "Sphere")]
 impl UnprocessedData for UnprocessedSphere {
-    fn process(&self) -> Box<dyn Hittable> {
-        Box::new(Sphere::new(self.center, self.radius, self.material))
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        Ok(Box::new(Sphere::new(self.center, self.radius, prototypes.material(&self.material)?)))
     }
 }
 
@@ -77,8 +187,268 @@ pub struct UnprocessedCylinder {
 
 #[typetag::serde(name = "Cylinder")]
 impl UnprocessedData for UnprocessedCylinder {
-    fn process(&self) -> Box<dyn Hittable> {
-        Box::new(Cylinder::new(self.center, self.radius, self.height, self.material))
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        Ok(Box::new(Cylinder::new(self.center, self.radius, self.height, prototypes.material(&self.material)?)))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnprocessedTriangle {
+    p0: Vec3,
+    p1: Vec3,
+    p2: Vec3,
+    #[serde(default)]
+    normals: Option<[Vec3; 3]>,
+    #[serde(default)]
+    uvs: Option<[(f64, f64); 3]>,
+    material: Material,
+}
+
+#[typetag::serde(name = "Triangle")]
+impl UnprocessedData for UnprocessedTriangle {
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        Ok(Box::new(Triangle::new(
+            [self.p0, self.p1, self.p2],
+            self.normals,
+            self.uvs.unwrap_or(DEFAULT_UVS),
+            prototypes.material(&self.material)?,
+        )))
+    }
+}
+
+/// Mesh written out in the config, checked while it is read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "MeshConfig", into = "MeshConfig")]
+pub struct UnprocessedMesh {
+    data: Arc<MeshData>,
+    material: Material,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MeshConfig {
+    vertices: Vec<Vec3>,
+    #[serde(default)]
+    normals: Vec<Vec3>,
+    #[serde(default)]
+    uvs: Vec<(f64, f64)>,
+    #[serde(default)]
+    colors: Vec<Color>,
+    faces: Vec<[usize; 3]>,
+    material: Material,
+}
+
+impl TryFrom<MeshConfig> for UnprocessedMesh {
+    type Error = crate::mesh::Error;
+
+    fn try_from(config: MeshConfig) -> Result<Self, Self::Error> {
+        Ok(UnprocessedMesh {
+            data: Arc::new(MeshData::new(config.vertices, config.normals, config.uvs, config.colors, config.faces)?),
+            material: config.material,
+        })
+    }
+}
+
+impl From<UnprocessedMesh> for MeshConfig {
+    fn from(mesh: UnprocessedMesh) -> Self {
+        let data = Arc::unwrap_or_clone(mesh.data);
+
+        MeshConfig {
+            vertices: data.vertices,
+            normals: data.normals,
+            uvs: data.uvs,
+            colors: data.colors,
+            faces: data.faces,
+            material: mesh.material,
+        }
+    }
+}
+
+#[typetag::serde(name = "Mesh")]
+impl UnprocessedData for UnprocessedMesh {
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        Ok(Box::new(Mesh::new(self.data.clone(), prototypes.material(&self.material)?)))
+    }
+}
+
+/// Model loaded from a Wavefront OBJ file, with the materials of its MTL library
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnprocessedObjFile {
+    path: ObjFile,
+    /// Used instead of the materials of the file
+    #[serde(default)]
+    material: Option<Material>,
+    /// Only these groups are loaded, all of them when empty
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+#[typetag::serde(name = "ObjFile")]
+impl UnprocessedData for UnprocessedObjFile {
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        let meshes = self
+            .path
+            .meshes
+            .iter()
+            .filter(|mesh| self.groups.is_empty() || self.groups.contains(&mesh.group))
+            .map(|mesh| {
+                let material = prototypes.material(self.material.as_ref().unwrap_or(&mesh.material))?;
+                Ok(Box::new(Mesh::new(mesh.data.clone(), material)) as Box<dyn Hittable>)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Box::new(Bvh::new(meshes)))
+    }
+}
+
+/// Scanned model loaded from a PLY file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnprocessedPlyFile {
+    path: PlyFile,
+    #[serde(default)]
+    transform: Transform,
+    material: Material,
+}
+
+#[typetag::serde(name = "PlyFile")]
+impl UnprocessedData for UnprocessedPlyFile {
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        let data = self.path.data.transformed(&self.transform.matrix());
+        Ok(Box::new(Mesh::new(Arc::new(data), prototypes.material(&self.material)?)))
+    }
+}
+
+/// CAD model loaded from an STL file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnprocessedStlFile {
+    path: StlFile,
+    #[serde(default)]
+    transform: Transform,
+    material: Material,
+}
+
+#[typetag::serde(name = "StlFile")]
+impl UnprocessedData for UnprocessedStlFile {
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        let data = self.path.data.transformed(&self.transform.matrix());
+        Ok(Box::new(Mesh::new(Arc::new(data), prototypes.material(&self.material)?)))
+    }
+}
+
+/// Scene loaded from a glTF file, with the meshes placed by its nodes
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnprocessedGltfFile {
+    path: GltfFile,
+    /// Applied on top of the transforms of the nodes
+    #[serde(default)]
+    transform: Transform,
+    /// Whether to look through the first camera of the file
+    #[serde(default)]
+    camera: bool,
+}
+
+#[typetag::serde(name = "GltfFile")]
+impl UnprocessedData for UnprocessedGltfFile {
+    fn process(&self, _prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        let matrix = self.transform.matrix();
+        let meshes = self
+            .path
+            .parts
+            .iter()
+            .map(|part| {
+                let data = part.data.transformed(&matrix);
+                Box::new(Mesh::new(Arc::new(data), part.material.clone())) as Box<dyn Hittable>
+            })
+            .collect();
+
+        Ok(Box::new(Bvh::new(meshes)))
+    }
+
+    fn camera(&self) -> Option<UnprocessedCamera> {
+        let camera = self.path.camera.filter(|_| self.camera)?;
+        let m = self.transform.matrix() * camera.transform;
+        let look_from = m.transform_point(&Vec3::default());
+
+        Some(UnprocessedCamera {
+            look_from,
+            look_at: look_from + m.transform_vector(&Vec3::new(0.0, 0.0, -1.0)).unit(),
+            vup: m.transform_vector(&Vec3::new(0.0, 1.0, 0.0)),
+            vfov: camera.vfov,
+            aperture: 0.0,
+        })
+    }
+}
+
+/// Copies of an object placed by transforms, which all share the one object
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnprocessedInstance {
+    object: Box<dyn UnprocessedData>,
+    transforms: Vec<Transform>,
+}
+
+#[typetag::serde(name = "Instance")]
+impl UnprocessedData for UnprocessedInstance {
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        Ok(instances(Arc::from(self.object.process(prototypes)?), &self.transforms))
+    }
+}
+
+/// Copies of a prototype of the config, placed like an `Instance`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnprocessedUse {
+    name: String,
+    transforms: Vec<Transform>,
+}
+
+#[typetag::serde(name = "Use")]
+impl UnprocessedData for UnprocessedUse {
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        Ok(instances(prototypes.get(&self.name)?, &self.transforms))
+    }
+}
+
+/// Entries moved together by the transform of the group, which applies after their own
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnprocessedGroup {
+    #[serde(default)]
+    transform: Transform,
+    children: Vec<Box<dyn UnprocessedData>>,
+}
+
+#[typetag::serde(name = "Group")]
+impl UnprocessedData for UnprocessedGroup {
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        let children = self
+            .children
+            .iter()
+            .map(|child| child.process(prototypes))
+            .collect::<Result<_, _>>()?;
+        let group: Box<dyn Hittable> = Box::new(Bvh::new(children));
+
+        if self.transform == Transform::default() {
+            return Ok(group);
+        }
+        Ok(instances(Arc::from(group), &[self.transform]))
+    }
+
+    fn camera(&self) -> Option<UnprocessedCamera> {
+        let camera = self.children.iter().find_map(|child| child.camera())?;
+        Some(camera.transformed(&self.transform.matrix()))
+    }
+}
+
+/// Solid carved or combined from two other solids
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnprocessedCsg {
+    operation: Operation,
+    a: Box<dyn UnprocessedData>,
+    b: Box<dyn UnprocessedData>,
+}
+
+#[typetag::serde(name = "Csg")]
+impl UnprocessedData for UnprocessedCsg {
+    fn process(&self, prototypes: &Prototypes) -> Result<Box<dyn Hittable>, Error> {
+        let csg = Csg::new(self.a.process(prototypes)?, self.b.process(prototypes)?, self.operation);
+        Ok(Box::new(csg.ok_or(Error::NotSolid)?))
     }
 }
 
@@ -92,6 +462,15 @@ pub struct UnprocessedCamera {
 }
 
 impl UnprocessedCamera {
+    fn transformed(&self, m: &Mat4) -> UnprocessedCamera {
+        UnprocessedCamera {
+            look_from: m.transform_point(&self.look_from),
+            look_at: m.transform_point(&self.look_at),
+            vup: m.transform_vector(&self.vup),
+            ..*self
+        }
+    }
+
     fn process(&self, height: u32, width: u32) -> Camera {
         Camera::new(
             self.look_from,
@@ -105,41 +484,168 @@ impl UnprocessedCamera {
     }
 }
 
+/// Structure used to find the closest object hit by a ray
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+pub enum Accelerator {
+    #[default]
+    Bvh,
+    List,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     #[serde(alias = "world")]
     unprocessed_data: Vec<Box<dyn UnprocessedData>>,
-    #[serde(alias = "camera")]
-    cam: UnprocessedCamera,
-    #[serde(alias = "light")]
-    light: i32,
+    /// Objects that the world places with `Use`, by name
+    #[serde(default)]
+    prototypes: HashMap<String, Box<dyn UnprocessedData>>,
+    /// Can be left out when a model brings its own camera
+    #[serde(alias = "camera", default)]
+    cam: Option<UnprocessedCamera>,
+    #[serde(default)]
+    exposure: f64,
+    /// Brightness from 0 to 100 of older configs, replaced by `exposure`
+    #[serde(default, skip_serializing)]
+    light: Option<i32>,
+    #[serde(default)]
+    tone_map: ToneMap,
     #[serde(alias = "samples")]
     samples: i32,
     #[serde(alias = "width")]
     width: u32,
     #[serde(alias = "height")]
     height: u32,
+    #[serde(default)]
+    threads: Option<usize>,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    accelerator: Accelerator,
+    #[serde(default)]
+    background: Background,
 }
 
 impl Config {
-    pub fn process(self) -> Application {
-        Application {
-            world: HittableList::new(self.unprocessed_data.iter().map(|d| d.process()).collect()),
-            camera: self.cam.process(self.width, self.height),
-            light: self.light,
+    pub fn process(self) -> Result<Application, Error> {
+        let prototypes = Prototypes::new(&self.prototypes);
+        let objects = self
+            .unprocessed_data
+            .iter()
+            .map(|d| d.process(&prototypes))
+            .collect::<Result<_, _>>()?;
+        let camera = self
+            .unprocessed_data
+            .iter()
+            .find_map(|d| d.camera())
+            .or(self.cam)
+            .ok_or(Error::MissingCamera)?;
+
+        // Old configs keep their brightness, values outside of 1-100 were ignored
+        let mut exposure = self.exposure;
+        if let Some(light) = self.light {
+            let stops = if (1..=100).contains(&light) { (light as f64 / 100.0).log2() } else { 0.0 };
+            exposure += stops;
+            eprintln!("`light` is deprecated, use `exposure: {exposure:.2}` instead");
+        }
+
+        Ok(Application {
+            world: match self.accelerator {
+                Accelerator::Bvh => Box::new(Bvh::new(objects)),
+                Accelerator::List => Box::new(HittableList::new(objects)),
+            },
+            camera: camera.process(self.width, self.height),
+            background: self.background,
+            exposure,
+            tone_map: self.tone_map,
             samples: self.samples,
             width: self.width,
             height: self.height,
-        }
+            seed: self.seed.unwrap_or_else(rand::random),
+            threads: self.threads.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |n| n.get())
+            }),
+        })
     }
 }
 
 #[derive(Debug)]
 pub struct Application {
-    pub world: HittableList,
+    pub world: Box<dyn Hittable>,
     pub camera: Camera,
-    pub light: i32,
+    pub background: Background,
+    /// Brightness adjustment in stops, every stop doubles the light
+    pub exposure: f64,
+    pub tone_map: ToneMap,
     pub samples: i32,
     pub height: u32,
     pub width: u32,
+    pub threads: usize,
+    /// Every random decision during rendering is derived from this
+    pub seed: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(prototypes: &str, world: &str) -> Result<Application, Error> {
+        let source = format!(
+            "Config(samples: 1, width: 4, height: 3, prototypes: {{ {prototypes} }}, world: [{world}],
+                camera: (look_from: Vec3(0.0, 0.0, 0.0), look_at: Vec3(0.0, 0.0, -1.0), vup: Vec3(0.0, 1.0, 0.0), vfov: 45.0, aperture: 0.0))"
+        );
+        ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_str::<Config>(&source)
+            .unwrap()
+            .process()
+    }
+
+    const BALL: &str = r#""ball": { "Sphere": (center: Vec3(0.0, 0.0, 0.0), radius: 1.0, material: Lambertian(albedo: Vec3(0.5, 0.5, 0.5))) }"#;
+
+    #[test]
+    fn test_light() {
+        let config = |settings: &str| {
+            let source = format!(
+                "Config(samples: 1, width: 4, height: 3, world: [], {settings}
+                    camera: (look_from: Vec3(0.0, 0.0, 0.0), look_at: Vec3(0.0, 0.0, -1.0), vup: Vec3(0.0, 1.0, 0.0), vfov: 45.0, aperture: 0.0))"
+            );
+            let options = ron::Options::default().with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME);
+            options.from_str::<Config>(&source).unwrap().process().unwrap().exposure
+        };
+
+        // Half the light is one stop down, on top of any exposure
+        assert_eq!(config("light: 50,"), -1.0);
+        assert_eq!(config("light: 25, exposure: 1.0,"), -1.0);
+        assert_eq!(config("light: 0,"), 0.0);
+        assert_eq!(config(""), 0.0);
+    }
+
+    #[test]
+    fn test_missing_texture() {
+        // Textures are loaded when the config is processed, and files that can't be read stop it
+        let world = r#"{ "Sphere": (center: Vec3(0.0, 0.0, 0.0), radius: 1.0, material: Lambertian(albedo: Image(path: "missing.png"))) }"#;
+        let error = process("", world).unwrap_err();
+        assert!(matches!(&error, Error::InvalidTexture(texture::LoadError(path, _)) if path.ends_with("missing.png")));
+        assert!(error.to_string().starts_with("failed to load texture missing.png"));
+    }
+
+    #[test]
+    fn test_prototypes() {
+        // A group of two balls, used twice by a group that is moved up
+        let pair = r#""pair": { "Group": (children: [
+            { "Use": (name: "ball", transforms: [(translate: Vec3(-2.0, 0.0, 0.0)), (translate: Vec3(2.0, 0.0, 0.0))]) },
+        ]) }"#;
+        let world = r#"{ "Group": (transform: (translate: Vec3(0.0, 5.0, 0.0)), children: [
+            { "Use": (name: "pair", transforms: [(), (translate: Vec3(0.0, 0.0, -10.0), scale: Vec3(2.0, 2.0, 2.0))]) },
+        ]) }"#;
+        let app = process(&format!("{BALL}, {pair}"), world).unwrap();
+        let bbox = app.world.bounding_box();
+        assert_eq!((bbox.min, bbox.max), (Vec3::new(-6.0, 3.0, -12.0), Vec3::new(6.0, 7.0, 1.0)));
+
+        let unknown = process(BALL, r#"{ "Use": (name: "chair", transforms: [()]) }"#);
+        assert!(matches!(unknown, Err(Error::UnknownPrototype(name)) if name == "chair"));
+
+        let recursive = process(r#""loop": { "Use": (name: "loop", transforms: [()]) }"#, r#"{ "Use": (name: "loop", transforms: [()]) }"#);
+        assert!(matches!(recursive, Err(Error::RecursivePrototype(name)) if name == "loop"));
+    }
 }
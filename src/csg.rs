@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::hit::*;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// How the solids of a `Csg` are put together
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Operation {
+    /// Everything inside either of them
+    Union,
+    /// Only what's inside both of them
+    Intersection,
+    /// The first one, with the second one carved out of it
+    Difference,
+}
+
+impl Operation {
+    fn contains(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            Operation::Union => in_a || in_b,
+            Operation::Intersection => in_a && in_b,
+            Operation::Difference => in_a && !in_b,
+        }
+    }
+}
+
+/// Solid made by combining two other solids, which can be combinations themselves
+#[derive(Debug)]
+pub struct Csg {
+    a: Box<dyn Hittable>,
+    b: Box<dyn Hittable>,
+    operation: Operation,
+    bbox: Aabb,
+}
+
+impl Csg {
+    /// `None` when one of the objects isn't a solid that can tell where rays go in and out of it
+    pub fn new(a: Box<dyn Hittable>, b: Box<dyn Hittable>, operation: Operation) -> Option<Csg> {
+        if !a.is_solid() || !b.is_solid() {
+            return None;
+        }
+
+        let (box_a, box_b) = (a.bounding_box(), b.bounding_box());
+        let bbox = match operation {
+            Operation::Union => box_a.union(&box_b),
+            Operation::Intersection => Aabb {
+                min: Vec3::max(&box_a.min, &box_b.min),
+                max: Vec3::min(&box_a.max, &box_b.max),
+            },
+            Operation::Difference => box_a,
+        };
+
+        Some(Csg {
+            a,
+            b,
+            operation,
+            bbox,
+        })
+    }
+
+    /// Surfaces of the second solid carved out of the first one face into it
+    fn orient<'a>(&self, mut rec: HitRecord<'a>, from_a: bool) -> HitRecord<'a> {
+        if self.operation == Operation::Difference && !from_a {
+            rec.normal = -rec.normal;
+            rec.geometric_normal = -rec.geometric_normal;
+        }
+        rec
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.intervals(r)?
+            .into_iter()
+            .flat_map(|i| [i.enter, i.exit])
+            .find(|rec| rec.t > t_min && rec.t < t_max)
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval<'_>>> {
+        // Walks along the ray past every surface of both solids, keeping track of which ones it's in
+        let mut crossings: Vec<(HitRecord, bool)> = Vec::new();
+        for (object, from_a) in [(&self.a, true), (&self.b, false)] {
+            for i in object.intervals(r)? {
+                crossings.push((i.enter, from_a));
+                crossings.push((i.exit, from_a));
+            }
+        }
+        crossings.sort_by(|x, y| x.0.t.total_cmp(&y.0.t));
+
+        let (mut in_a, mut in_b) = (false, false);
+        let mut enter = None;
+        let mut intervals = Vec::new();
+        for (rec, from_a) in crossings {
+            if from_a {
+                in_a = !in_a;
+            } else {
+                in_b = !in_b;
+            }
+
+            match (enter, self.operation.contains(in_a, in_b)) {
+                (None, true) => enter = Some(self.orient(rec, from_a)),
+                (Some(start), false) => {
+                    intervals.push(Interval {
+                        enter: start,
+                        exit: self.orient(rec, from_a),
+                    });
+                    enter = None;
+                }
+                _ => {}
+            }
+        }
+
+        Some(intervals)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::Cube;
+    use crate::cylinder::Cylinder;
+    use crate::material::Material;
+    use crate::plane_surf::Plane;
+    use crate::sphere::Sphere;
+
+    fn sphere(x: f64) -> Box<dyn Hittable> {
+        Box::new(Sphere::new(Vec3::new(x, 0.0, 0.0), 1.0, Material::default()))
+    }
+
+    /// Distances and normals along x of the surfaces that a ray down the x axis passes
+    fn crossings(csg: &Csg) -> Vec<(f64, f64)> {
+        let ray = Ray::new(Vec3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        csg.intervals(&ray)
+            .unwrap()
+            .iter()
+            .flat_map(|i| [i.enter, i.exit])
+            .map(|rec| ((rec.t * 1e9).round() / 1e9, rec.geometric_normal.x()))
+            .collect()
+    }
+
+    #[test]
+    fn test_operations() {
+        // Spheres from -1 to 1 and from 0 to 2 along x
+        let csg = |operation| Csg::new(sphere(0.0), sphere(1.0), operation).unwrap();
+
+        assert_eq!(crossings(&csg(Operation::Union)), [(9.0, -1.0), (12.0, 1.0)]);
+        assert_eq!(crossings(&csg(Operation::Intersection)), [(10.0, -1.0), (11.0, 1.0)]);
+        // The carved face is the inside of the second sphere, so its normal points back into the hole
+        assert_eq!(crossings(&csg(Operation::Difference)), [(9.0, -1.0), (10.0, 1.0)]);
+
+        let bbox = csg(Operation::Intersection).bounding_box();
+        assert_eq!((bbox.min.x(), bbox.max.x()), (0.0, 1.0));
+    }
+
+    #[test]
+    fn test_hit() {
+        // A cube with a hole drilled through it from top to bottom
+        let cube = Box::new(Cube::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0), Material::default()));
+        let drill = Box::new(Cylinder::new(Vec3::new(0.0, -2.0, 0.0), 0.5, 4.0, Material::default()));
+
+        // Along its axis, the drill goes from cap to cap
+        let down = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let along = drill.intervals(&down).unwrap();
+        assert_eq!((along[0].enter.t, along[0].enter.geometric_normal), (3.0, Vec3::new(0.0, 1.0, 0.0)));
+        assert_eq!((along[0].exit.t, along[0].exit.geometric_normal), (7.0, Vec3::new(0.0, -1.0, 0.0)));
+        let csg = Csg::new(cube, drill, Operation::Difference).unwrap();
+
+        // Down the hole the ray passes through, across it the walls of the hole face inwards
+        assert!(csg.hit(&down, 0.0, f64::MAX).is_none());
+        let rec = csg.hit(&Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), 0.0, f64::MAX).unwrap();
+        assert_eq!((rec.t, rec.geometric_normal), (4.0, Vec3::new(-1.0, 0.0, 0.0)));
+        let rec = csg.hit(&Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), 4.0, f64::MAX).unwrap();
+        assert_eq!((rec.t, rec.geometric_normal), (4.5, Vec3::new(1.0, 0.0, 0.0)));
+
+        // From inside the solid part, the ray hits the wall of the hole
+        let rec = csg.hit(&Ray::new(Vec3::new(-0.75, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), 0.0, f64::MAX).unwrap();
+        assert_eq!(rec.t, 0.25);
+
+        let plane = Box::new(Plane::new(Vec3::new(0.0, 1.0, 0.0), 0.0, 1.0, 1.0, Material::default()));
+        assert!(Csg::new(sphere(0.0), plane, Operation::Union).is_none());
+    }
+}
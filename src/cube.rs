@@ -1,4 +1,5 @@
-use crate::hit::{HitRecord, Hittable};
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable, Interval};
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
@@ -16,8 +17,10 @@ impl Cube {
     }
 }
 
-impl Hittable for Cube {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+impl Cube {
+    /// Where the line of the ray goes in and out of the box, the box is missed when they're in the
+    /// wrong order
+    fn slabs(&self, r: &Ray) -> (f64, f64) {
         let t1 = (self.min.x() - r.origin.x()) / r.direction.x();
         let t2 = (self.max.x() - r.origin.x()) / r.direction.x();
 
@@ -27,39 +30,168 @@ impl Hittable for Cube {
         let t5 = (self.min.z() - r.origin.z()) / r.direction.z();
         let t6 = (self.max.z() - r.origin.z()) / r.direction.z();
 
-        let t_min = t_min.max(t1.min(t2)).max(t3.min(t4)).max(t5.min(t6));
-        let t_max = t_max.min(t1.max(t2)).min(t3.max(t4)).min(t5.max(t6));
-
-        if t_max > t_min {
-            let t = t_min;
-            let p = r.at(t);
-
-            let normal = if p.x() < self.min.x() + 0.00001 {
-                Vec3::new(-1.0, 0.0, 0.0)
-            } else if p.x() > self.max.x() - 0.00001 {
-                Vec3::new(1.0, 0.0, 0.0)
-            } else if p.y() < self.min.y() + 0.00001 {
-                Vec3::new(0.0, -1.0, 0.0)
-            } else if p.y() > self.max.y() - 0.00001 {
-                Vec3::new(0.0, 1.0, 0.0)
-            } else if p.z() < self.min.z() + 0.00001 {
-                Vec3::new(0.0, 0.0, -1.0)
-            } else if p.z() > self.max.z() - 0.00001 {
-                Vec3::new(0.0, 0.0, 1.0)
-            } else {
-                Vec3::new(0.0, 0.0, 0.0)
-            };
+        let t_enter = t1.min(t2).max(t3.min(t4)).max(t5.min(t6));
+        let t_exit = t1.max(t2).min(t3.max(t4)).min(t5.max(t6));
+
+        (t_enter, t_exit)
+    }
+
+    fn record(&self, r: &Ray, t: f64) -> HitRecord<'_> {
+        let p = r.at(t);
+
+        // Position within the box, 0.0-1.0 along every axis
+        let size = self.max - self.min;
+        let (x, y, z) = (
+            (p.x() - self.min.x()) / size.x(),
+            (p.y() - self.min.y()) / size.y(),
+            (p.z() - self.min.z()) / size.z(),
+        );
+
+        // Seen from outside, u goes to the right and v up on the side faces. On the top and bottom
+        // u follows +x, and v points away from the +z face
+        let (sx, sy, sz) = (
+            Vec3::new(size.x(), 0.0, 0.0),
+            Vec3::new(0.0, size.y(), 0.0),
+            Vec3::new(0.0, 0.0, size.z()),
+        );
+        // The face closest to the point, which rounding may have put just off the box
+        let faces = [
+            (p.x() - self.min.x(), Vec3::new(-1.0, 0.0, 0.0), (z, y), (sz, sy)),
+            (self.max.x() - p.x(), Vec3::new(1.0, 0.0, 0.0), (1.0 - z, y), (-sz, sy)),
+            (p.y() - self.min.y(), Vec3::new(0.0, -1.0, 0.0), (x, 1.0 - z), (sx, -sz)),
+            (self.max.y() - p.y(), Vec3::new(0.0, 1.0, 0.0), (x, 1.0 - z), (sx, -sz)),
+            (p.z() - self.min.z(), Vec3::new(0.0, 0.0, -1.0), (1.0 - x, y), (-sx, sy)),
+            (self.max.z() - p.z(), Vec3::new(0.0, 0.0, 1.0), (x, y), (sx, sy)),
+        ];
+        let (_, normal, (u, v), (dpdu, dpdv)) = faces
+            .into_iter()
+            .min_by(|a, b| a.0.abs().total_cmp(&b.0.abs()))
+            .unwrap();
+
+        HitRecord {
+            t,
+            point: p,
+            u,
+            v,
+            normal,
+            geometric_normal: normal,
+            dpdu,
+            dpdv,
+            color: None,
+            material: &self.material,
+        }
+    }
+}
+
+impl Hittable for Cube {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t_enter, t_exit) = self.slabs(r);
 
-            Some(HitRecord {
-                t,
-                point: p,
-                u: 1.0,
-                v: 1.0,
-                normal,
-                material: self.material,
-            })
+        // Rays starting inside the box hit it where they leave
+        let t = if t_enter > t_min { t_enter } else { t_exit };
+
+        if t_enter <= t_exit && t > t_min && t < t_max {
+            Some(self.record(r, t))
         } else {
             None
         }
     }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval<'_>>> {
+        let (t_enter, t_exit) = self.slabs(r);
+        if t_enter > t_exit {
+            return Some(Vec::new());
+        }
+
+        Some(vec![Interval {
+            enter: self.record(r, t_enter),
+            exit: self.record(r, t_exit),
+        }])
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.min, self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uv() {
+        let cube = Cube::new(Vec3::new(-1.0, 0.0, -2.0), Vec3::new(1.0, 4.0, 2.0), Material::default());
+        let uv = |origin: Vec3, direction: Vec3| {
+            let rec = cube.hit(&Ray::new(origin, direction), 0.0, f64::MAX).unwrap();
+            (rec.u, rec.v)
+        };
+
+        // Front face, a quarter from its left edge and at three quarters of its height
+        let (u, v) = uv(Vec3::new(-0.5, 3.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!((u - 0.25).abs() < 1e-9 && (v - 0.75).abs() < 1e-9);
+
+        // The right side, seen from the right, starts at the front edge
+        let (u, v) = uv(Vec3::new(10.0, 1.0, 1.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert!((u - 0.25).abs() < 1e-9 && (v - 0.25).abs() < 1e-9);
+
+        // Top, v grows away from the front
+        let (u, v) = uv(Vec3::new(0.5, 10.0, -1.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((u - 0.75).abs() < 1e-9 && (v - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_hit_from_inside() {
+        let cube = Cube::new(Vec3::new(-1.0, 0.0, -2.0), Vec3::new(1.0, 4.0, 2.0), Material::default());
+        let rec = cube.hit(&Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), 0.0, f64::MAX).unwrap();
+
+        assert_eq!(rec.t, 3.0);
+        assert_eq!(rec.geometric_normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!(cube.hit(&Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), 0.0, 2.0).is_none());
+    }
+
+    #[test]
+    fn test_nearest_face() {
+        let cube = Cube::new(Vec3::new(-1.0, 0.0, -2.0), Vec3::new(1.0, 4.0, 2.0), Material::default());
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        // Points off the faces still get the normal of the face they're closest to
+        for t in [0.9, 1.0 + 1e-3] {
+            let rec = cube.record(&ray, t);
+            assert_eq!(rec.geometric_normal, Vec3::new(1.0, 0.0, 0.0));
+            assert_eq!(rec.dpdu, Vec3::new(0.0, 0.0, -4.0));
+        }
+    }
+
+    #[test]
+    fn test_tangents() {
+        let cube = Cube::new(Vec3::new(-1.0, 0.0, -2.0), Vec3::new(1.0, 4.0, 2.0), Material::default());
+        let origins = [
+            Vec3::new(10.0, 1.0, 1.0),
+            Vec3::new(-10.0, 1.0, 1.0),
+            Vec3::new(0.5, 10.0, 1.0),
+            Vec3::new(0.5, -10.0, 1.0),
+            Vec3::new(0.5, 1.0, 10.0),
+            Vec3::new(0.5, 1.0, -10.0),
+        ];
+
+        for origin in origins {
+            let target = Vec3::new(0.5, 1.0, 1.0);
+            let rec = cube.hit(&Ray::new(origin, target - origin), 0.0, f64::MAX).unwrap();
+
+            // The tangents lie in the face, and a step along them moves as far in u or v as in the texture
+            assert_eq!(Vec3::dot(&rec.dpdu, &rec.normal), 0.0);
+            assert_eq!(Vec3::dot(&rec.dpdv, &rec.normal), 0.0);
+            let moved = |d: Vec3| {
+                let p = rec.point + 0.1 * d;
+                cube.hit(&Ray::new(p + rec.normal, -rec.normal), 0.0, f64::MAX).unwrap()
+            };
+            let (along_u, along_v) = (moved(rec.dpdu), moved(rec.dpdv));
+            assert!((along_u.u - rec.u - 0.1).abs() < 1e-9 && (along_u.v - rec.v).abs() < 1e-9);
+            assert!((along_v.v - rec.v - 0.1).abs() < 1e-9 && (along_v.u - rec.u).abs() < 1e-9);
+        }
+    }
 }
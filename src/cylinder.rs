@@ -1,3 +1,4 @@
+use crate::aabb::Aabb;
 use crate::hit::*;
 use crate::material::Material;
 use crate::ray::Ray;
@@ -29,11 +30,83 @@ impl Cylinder {
             material,
         }
     }
+
+    /// Angle around the axis, starting at -x like on spheres, and height along the side
+    fn side_uv(&self, point: &Vec3) -> (f64, f64) {
+        let p = *point - self.center;
+        let phi = (-p.z()).atan2(p.x()) + std::f64::consts::PI;
+
+        (phi / (2.0 * std::f64::consts::PI), p.y() / self.height)
+    }
+
+    /// Caps are laid out like floors, u along +x and v towards -z
+    fn cap_uv(&self, point: &Vec3) -> (f64, f64) {
+        let p = *point - self.center;
+
+        (p.x() / (2.0 * self.radius) + 0.5, -p.z() / (2.0 * self.radius) + 0.5)
+    }
+
+    /// Outward normal and derivatives of the point along u and v on the side
+    fn side_frame(&self, point: &Vec3) -> (Vec3, Vec3, Vec3) {
+        let p = *point - self.center;
+
+        (
+            Vec3::new(p.x(), 0.0, p.z()) / self.radius,
+            2.0 * std::f64::consts::PI * Vec3::new(p.z(), 0.0, -p.x()),
+            Vec3::new(0.0, self.height, 0.0),
+        )
+    }
+
+    fn cap_tangents(&self) -> (Vec3, Vec3) {
+        (
+            Vec3::new(2.0 * self.radius, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -2.0 * self.radius),
+        )
+    }
+
+    fn side_record(&self, r: &Ray, t: f64) -> HitRecord<'_> {
+        let point = r.at(t);
+        let (u, v) = self.side_uv(&point);
+        let (normal, dpdu, dpdv) = self.side_frame(&point);
+
+        HitRecord {
+            t,
+            point,
+            normal,
+            geometric_normal: Vec3::unit_vector(&normal),
+            u,
+            v,
+            dpdu,
+            dpdv,
+            color: None,
+            material: &self.material,
+        }
+    }
+
+    fn cap_record(&self, r: &Ray, t: f64, top: bool) -> HitRecord<'_> {
+        let point = r.at(t);
+        let (u, v) = self.cap_uv(&point);
+        let (dpdu, dpdv) = self.cap_tangents();
+        let normal = Vec3::new(0.0, if top { 1.0 } else { -1.0 }, 0.0);
+
+        HitRecord {
+            t,
+            point,
+            normal,
+            geometric_normal: normal,
+            u,
+            v,
+            dpdu,
+            dpdv,
+            color: None,
+            material: &self.material,
+        }
+    }
 }
 
 // https://www.youtube.com/watch?v=UTz7ytMJ2yk
 impl Hittable for Cylinder {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         // let t = (0.0001 - r.origin.y()) / r.direction.y();
         let oc = r.origin - self.center;
 
@@ -57,18 +130,9 @@ impl Hittable for Cylinder {
 
         let mut poi = oc + t_array[2] * r.direction;
 
-        let mut normal = (r.at(t_array[2]) - self.center) / self.radius;
-
         if t_array[2] > t_min && t_array[2] < t_max && poi.dot_xz(&poi) < self.radius * self.radius{ 
             valid_array[2] = true;
-            poi_array[2] = Some(HitRecord {
-                t: t_array[2],
-                point: r.at(t_array[2]),
-                normal,
-                u: 0.0,
-                v: 0.0,
-                material: self.material,
-            });
+            poi_array[2] = Some(self.cap_record(r, t_array[2], false));
         } else {
             t_array[2] = 100e6;
         }
@@ -76,18 +140,9 @@ impl Hittable for Cylinder {
         
         poi = oc + t_array[3] * r.direction;
 
-        normal = (r.at(t_array[3]) - self.center) / self.radius;
-
         if t_array[3] > t_min && t_array[3] < t_max && poi.dot_xz(&poi) < self.radius * self.radius{ 
             valid_array[3] = true;
-            poi_array[3] = Some(HitRecord {
-                t: t_array[3],
-                point: r.at(t_array[3]),
-                normal,
-                u: 0.0,
-                v: 0.0,
-                material: self.material,
-            });
+            poi_array[3] = Some(self.cap_record(r, t_array[3], true));
         } else {
             t_array[3] = 100e6;
         }
@@ -102,14 +157,7 @@ impl Hittable for Cylinder {
             
             if t_array[0] < t_max && t_array[0] > t_min && y >= self.center.y() - 0.0001 && y <= self.center.y() + self.height + 0.0001 {
                 valid_array[0] = true;
-                poi_array[0] = Some(HitRecord {
-                    t: t_array[0],
-                    point: r.at(t_array[0]),
-                    normal: (r.at(t_array[0]) - self.center) / self.radius,
-                    u: 0.0,
-                    v: 0.0,
-                    material: self.material,
-                });
+                poi_array[0] = Some(self.side_record(r, t_array[0]));
                 // }
             } else {
                 t_array[0] = 100e6;
@@ -119,14 +167,7 @@ impl Hittable for Cylinder {
 
             if t_array[1] < t_max && t_array[1] > t_min && y >= self.center.y() - 0.0001 && y <= self.center.y() + self.height + 0.0001 {
                 valid_array[1] = true;
-                poi_array[1] = Some(HitRecord {
-                    t: t_array[1],
-                    point: r.at(t_array[1]),
-                    normal: (r.at(t_array[1]) - self.center) / self.radius,
-                    u: 0.0,
-                    v: 0.0,
-                    material: self.material,
-                });
+                poi_array[1] = Some(self.side_record(r, t_array[1]));
             } else {
                 t_array[1] = 100e6;
             }
@@ -146,9 +187,66 @@ impl Hittable for Cylinder {
             }
         }
 
-        let valid_poi = poi_array[min_index];
-        return valid_poi;
+        poi_array[min_index]
+
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval<'_>>> {
+        let oc = r.origin - self.center;
+        let a = r.direction.dot_xz(&r.direction);
+        let b = oc.dot_xz(&r.direction);
+        let c = oc.dot_xz(&oc) - self.radius * self.radius;
+
+        // Stretch inside the endless tube around the axis, which rays along the axis are in or out of
+        // all the way
+        let (side_in, side_out) = if a == 0.0 {
+            if c > 0.0 {
+                return Some(Vec::new());
+            }
+            (f64::NEG_INFINITY, f64::INFINITY)
+        } else {
+            let discriminant = b * b - a * c;
+            if discriminant <= 0.0 {
+                return Some(Vec::new());
+            }
+            ((-b - discriminant.sqrt()) / a, (-b + discriminant.sqrt()) / a)
+        };
+
+        // Stretch between the planes of the caps, with whether it goes in through the top
+        let (bottom, top) = (-oc.y() / r.direction.y(), (self.height - oc.y()) / r.direction.y());
+        let (cap_in, cap_out, in_at_top) = if r.direction.y() == 0.0 {
+            if oc.y() < 0.0 || oc.y() > self.height {
+                return Some(Vec::new());
+            }
+            (f64::NEG_INFINITY, f64::INFINITY, false)
+        } else if bottom < top {
+            (bottom, top, false)
+        } else {
+            (top, bottom, true)
+        };
+
+        let enter = if side_in > cap_in { self.side_record(r, side_in) } else { self.cap_record(r, cap_in, in_at_top) };
+        let exit = if side_out < cap_out { self.side_record(r, side_out) } else { self.cap_record(r, cap_out, !in_at_top) };
+        if enter.t >= exit.t {
+            return Some(Vec::new());
+        }
+
+        Some(vec![Interval { enter, exit }])
+    }
 
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(
+            Vec3::new(self.center.x() - self.radius, self.center.y(), self.center.z() - self.radius),
+            Vec3::new(
+                self.center.x() + self.radius,
+                self.center.y() + self.height,
+                self.center.z() + self.radius,
+            ),
+        )
     }
 }
 
@@ -0,0 +1,251 @@
+use std::f64::consts::PI;
+use std::path::PathBuf;
+
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::image::{Error, Image};
+use crate::vec3::Vec3;
+
+/// Piecewise constant distribution over [0, 1)
+#[derive(Debug, Clone)]
+struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    fn new(func: Vec<f64>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Returns the sampled position, its density and the index of the segment it falls in
+    fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.func.len();
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(n - 1);
+
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            (u - self.cdf[index]) / width
+        } else {
+            0.0
+        };
+
+        let pdf = if self.integral > 0.0 {
+            self.func[index] / self.integral
+        } else {
+            0.0
+        };
+
+        ((index as f64 + offset) / n as f64, pdf, index)
+    }
+}
+
+/// Distribution over the unit square, sampled row first and then column within the row
+#[derive(Debug, Clone)]
+struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    fn new(func: &[f64], width: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = func
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|c| c.integral).collect());
+
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    fn sample(&self, u: f64, v: f64) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample(v);
+        let (x, pdf_x, _) = self.conditional[row].sample(u);
+
+        ((x, y), pdf_x * pdf_y)
+    }
+}
+
+/// Equirectangular environment image, with a distribution for sampling it by luminance
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "PathBuf", into = "PathBuf")]
+pub struct EnvironmentMap {
+    path: PathBuf,
+    image: Image,
+    distribution: Distribution2D,
+}
+
+impl TryFrom<PathBuf> for EnvironmentMap {
+    type Error = Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        let image = Image::load(&path)?;
+        if image.width == 0 || image.height == 0 {
+            return Err(Error::InvalidData("environment map is empty"));
+        }
+
+        Ok(EnvironmentMap::new(path, image))
+    }
+}
+
+impl From<EnvironmentMap> for PathBuf {
+    fn from(map: EnvironmentMap) -> Self {
+        map.path
+    }
+}
+
+fn luminance(c: Color) -> f64 {
+    0.2126 * c.r() + 0.7152 * c.g() + 0.0722 * c.b()
+}
+
+impl EnvironmentMap {
+    fn new(path: PathBuf, image: Image) -> EnvironmentMap {
+        // Rows near the poles cover less of the sphere, so they are weighted by sin(theta)
+        let func: Vec<f64> = image
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(i, &pixel)| {
+                let theta = PI * ((i / image.width) as f64 + 0.5) / image.height as f64;
+                luminance(pixel) * theta.sin()
+            })
+            .collect();
+
+        EnvironmentMap {
+            distribution: Distribution2D::new(&func, image.width),
+            path,
+            image,
+        }
+    }
+
+    /// Image coordinates of a direction; the centre of the image faces -z and the top faces +y
+    fn direction_to_uv(direction: &Vec3, rotation: f64) -> (f64, f64) {
+        let d = Vec3::unit_vector(direction);
+        let phi = d.x().atan2(-d.z()) - rotation.to_radians();
+
+        (
+            (phi / (2.0 * PI) + 0.5).rem_euclid(1.0),
+            d.y().clamp(-1.0, 1.0).acos() / PI,
+        )
+    }
+
+    fn uv_to_direction(u: f64, v: f64, rotation: f64) -> Vec3 {
+        let phi = (u - 0.5) * 2.0 * PI + rotation.to_radians();
+        let theta = v * PI;
+
+        Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+    }
+
+    fn lookup(&self, u: f64, v: f64) -> Color {
+        let x = ((u * self.image.width as f64) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as usize).min(self.image.height - 1);
+
+        self.image.get(x, y)
+    }
+
+    /// Radiance coming from `direction`, with the map rotated around the y axis by `rotation` degrees
+    pub fn color(&self, direction: &Vec3, rotation: f64) -> Color {
+        let (u, v) = EnvironmentMap::direction_to_uv(direction, rotation);
+        self.lookup(u, v)
+    }
+
+    /// Picks a direction proportionally to the brightness of the map.
+    /// Returns the unit direction, the radiance coming from it and its solid angle density
+    pub fn sample(&self, rotation: f64, rng: &mut impl Rng) -> Option<(Vec3, Color, f64)> {
+        let ((u, v), pdf) = self.distribution.sample(rng.gen(), rng.gen());
+        let sin_theta = (v * PI).sin();
+
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+
+        Some((
+            EnvironmentMap::uv_to_direction(u, v, rotation),
+            self.lookup(u, v),
+            pdf / (2.0 * PI * PI * sin_theta),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_direction_uv_roundtrip() {
+        for direction in [
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.5, 0.3),
+            Vec3::new(-0.2, -0.9, 0.4),
+        ] {
+            let (u, v) = EnvironmentMap::direction_to_uv(&direction, 30.0);
+            let back = EnvironmentMap::uv_to_direction(u, v, 30.0);
+            let expected = Vec3::unit_vector(&direction);
+
+            assert!((back - expected).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_sample_picks_bright_pixel() {
+        let mut pixels = vec![Color::new(0.01, 0.01, 0.01); 16 * 8];
+        pixels[3 * 16 + 5] = Color::new(1000.0, 1000.0, 1000.0);
+        let map = EnvironmentMap::new(PathBuf::new(), Image::new(16, 8, pixels));
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let hits = (0..1000)
+            .filter_map(|_| map.sample(0.0, &mut rng))
+            .filter(|(_, radiance, _)| radiance.r() > 1.0)
+            .count();
+
+        assert!(hits > 950);
+    }
+
+    #[test]
+    fn test_sample_pdf_integrates_to_one() {
+        let pixels = (0..32 * 16)
+            .map(|i| Color::new((i % 7) as f64, (i % 5) as f64, 1.0))
+            .collect();
+        let map = EnvironmentMap::new(PathBuf::new(), Image::new(32, 16, pixels));
+        let mut rng = StdRng::seed_from_u64(1);
+
+        // E[1 / pdf] over the sampled directions is the area of the unit sphere
+        let n = 20000;
+        let estimate: f64 = (0..n)
+            .filter_map(|_| map.sample(0.0, &mut rng))
+            .map(|(_, _, pdf)| 1.0 / pdf)
+            .sum::<f64>()
+            / n as f64;
+
+        assert!((estimate - 4.0 * PI).abs() < 0.05 * 4.0 * PI);
+    }
+}
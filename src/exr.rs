@@ -0,0 +1,286 @@
+use std::io::Write;
+
+use clap::ValueEnum;
+use flate2::write::ZlibEncoder;
+use half::f16;
+
+use crate::render::Framebuffer;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: [u8; 4] = [2, 0, 0, 0]; // Version 2, single part scanline image
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum PixelType {
+    #[default]
+    Half,
+    Float,
+}
+
+impl PixelType {
+    fn id(self) -> i32 {
+        match self {
+            PixelType::Half => 1,
+            PixelType::Float => 2,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PixelType::Half => 2,
+            PixelType::Float => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum Compression {
+    None,
+    #[default]
+    Zip,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zip => 3,
+        }
+    }
+
+    /// Amount of scanlines stored together in one chunk
+    fn lines_per_chunk(self) -> usize {
+        match self {
+            Compression::None => 1,
+            Compression::Zip => 16,
+        }
+    }
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend(name.as_bytes());
+    header.push(0);
+    header.extend(kind.as_bytes());
+    header.push(0);
+    header.extend((value.len() as i32).to_le_bytes());
+    header.extend(value);
+}
+
+fn header(framebuffer: &Framebuffer, channels: &[char], pixel_type: PixelType, compression: Compression) -> Vec<u8> {
+    let mut channel_list = Vec::new();
+    for &channel in channels {
+        channel_list.push(channel as u8);
+        channel_list.push(0);
+        channel_list.extend(pixel_type.id().to_le_bytes());
+        channel_list.extend([0, 0, 0, 0]); // pLinear and reserved bytes
+        channel_list.extend(1i32.to_le_bytes()); // x sampling
+        channel_list.extend(1i32.to_le_bytes()); // y sampling
+    }
+    channel_list.push(0);
+
+    let window: Vec<u8> = [0, 0, framebuffer.width as i32 - 1, framebuffer.height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+
+    let mut header = Vec::new();
+    header.extend(MAGIC);
+    header.extend(VERSION);
+    attribute(&mut header, "channels", "chlist", &channel_list);
+    attribute(&mut header, "compression", "compression", &[compression.id()]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+
+    header
+}
+
+/// Reorders the bytes and stores differences between them, which makes the data compress better
+fn zip_predict(raw: &[u8]) -> Vec<u8> {
+    let half = raw.len().div_ceil(2);
+    let mut out = vec![0; raw.len()];
+    for (i, &byte) in raw.iter().enumerate() {
+        out[if i % 2 == 0 { i / 2 } else { half + i / 2 }] = byte;
+    }
+
+    for i in (1..out.len()).rev() {
+        out[i] = out[i].wrapping_sub(out[i - 1]).wrapping_add(128);
+    }
+
+    out
+}
+
+fn compress(raw: Vec<u8>, compression: Compression) -> std::io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(raw),
+        Compression::Zip => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&zip_predict(&raw))?;
+            let compressed = encoder.finish()?;
+
+            // Chunks that don't get smaller are stored as they are
+            Ok(if compressed.len() < raw.len() {
+                compressed
+            } else {
+                raw
+            })
+        }
+    }
+}
+
+/// Writes a scanline OpenEXR image with linear RGB, and the coverage as alpha when `alpha` is set
+pub fn write_exr(
+    out: &mut impl Write,
+    framebuffer: &Framebuffer,
+    alpha: bool,
+    pixel_type: PixelType,
+    compression: Compression,
+) -> std::io::Result<()> {
+    // Channels are stored in alphabetical order
+    let channels: &[char] = if alpha {
+        &['A', 'B', 'G', 'R']
+    } else {
+        &['B', 'G', 'R']
+    };
+    let width = framebuffer.width as usize;
+    let height = framebuffer.height as usize;
+    let lines_per_chunk = compression.lines_per_chunk();
+
+    let value = |x: usize, y: usize, channel: char| {
+        let index = y * width + x;
+        // With alpha, the colour is premultiplied and leaves the background out
+        let pixel = if alpha {
+            framebuffer.foreground(index)
+        } else {
+            framebuffer.pixels[index]
+        };
+
+        match channel {
+            'R' => pixel.r(),
+            'G' => pixel.g(),
+            'B' => pixel.b(),
+            _ => framebuffer.alpha[index],
+        }
+    };
+
+    let mut chunks = Vec::new();
+    for first in (0..height).step_by(lines_per_chunk) {
+        let mut raw = Vec::with_capacity(lines_per_chunk * width * channels.len() * pixel_type.size());
+
+        for y in first..(first + lines_per_chunk).min(height) {
+            for &channel in channels {
+                for x in 0..width {
+                    let v = value(x, y, channel) as f32;
+                    match pixel_type {
+                        PixelType::Half => raw.extend(f16::from_f32(v).to_le_bytes()),
+                        PixelType::Float => raw.extend(v.to_le_bytes()),
+                    }
+                }
+            }
+        }
+
+        chunks.push((first, compress(raw, compression)?));
+    }
+
+    let header = header(framebuffer, channels, pixel_type, compression);
+    out.write_all(&header)?;
+
+    // Offset table pointing at the start of every chunk
+    let mut offset = (header.len() + 8 * chunks.len()) as u64;
+    for (_, data) in &chunks {
+        out.write_all(&offset.to_le_bytes())?;
+        offset += 8 + data.len() as u64;
+    }
+
+    for (y, data) in &chunks {
+        out.write_all(&(*y as i32).to_le_bytes())?;
+        out.write_all(&(data.len() as i32).to_le_bytes())?;
+        out.write_all(data)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    fn framebuffer() -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(3, 20);
+        for (i, pixel) in framebuffer.pixels.iter_mut().enumerate() {
+            *pixel = Color::new(i as f64, 0.5, 4.0);
+        }
+
+        framebuffer
+    }
+
+    fn unpredict(data: &[u8]) -> Vec<u8> {
+        let mut t = data.to_vec();
+        for i in 1..t.len() {
+            t[i] = t[i].wrapping_add(t[i - 1]).wrapping_sub(128);
+        }
+
+        let half = t.len().div_ceil(2);
+        (0..t.len())
+            .map(|i| if i % 2 == 0 { t[i / 2] } else { t[half + i / 2] })
+            .collect()
+    }
+
+    #[test]
+    fn test_zip_predict_roundtrip() {
+        let raw: Vec<u8> = (0..101).map(|i| (i * 37 % 256) as u8).collect();
+        assert_eq!(unpredict(&zip_predict(&raw)), raw);
+    }
+
+    #[test]
+    fn test_write_exr_uncompressed_float() {
+        let framebuffer = framebuffer();
+        let mut out = Vec::new();
+        write_exr(&mut out, &framebuffer, false, PixelType::Float, Compression::None).unwrap();
+
+        assert_eq!(out[..4], MAGIC);
+        let header_len = header(&framebuffer, &['B', 'G', 'R'], PixelType::Float, Compression::None).len();
+        let first_chunk = u64::from_le_bytes(out[header_len..header_len + 8].try_into().unwrap()) as usize;
+        assert_eq!(first_chunk, header_len + 8 * 20);
+
+        // Second scanline: y, size, then the blue channel of every pixel
+        let second_chunk = first_chunk + 8 + 3 * 3 * 4;
+        let chunk = &out[second_chunk..];
+        assert_eq!(i32::from_le_bytes(chunk[..4].try_into().unwrap()), 1);
+        assert_eq!(i32::from_le_bytes(chunk[4..8].try_into().unwrap()), 3 * 3 * 4);
+        assert_eq!(f32::from_le_bytes(chunk[8..12].try_into().unwrap()), 4.0);
+        // Red channel of the first pixel in the row
+        assert_eq!(f32::from_le_bytes(chunk[8 + 24..8 + 28].try_into().unwrap()), 3.0);
+    }
+
+    #[test]
+    fn test_write_exr_zip_half() {
+        let framebuffer = framebuffer();
+        let mut out = Vec::new();
+        write_exr(&mut out, &framebuffer, true, PixelType::Half, Compression::Zip).unwrap();
+
+        let header_len = header(&framebuffer, &['A', 'B', 'G', 'R'], PixelType::Half, Compression::Zip).len();
+        let first_chunk = u64::from_le_bytes(out[header_len..header_len + 8].try_into().unwrap()) as usize;
+        let size = i32::from_le_bytes(out[first_chunk + 4..first_chunk + 8].try_into().unwrap()) as usize;
+
+        let mut predicted = Vec::new();
+        ZlibDecoder::new(&out[first_chunk + 8..first_chunk + 8 + size])
+            .read_to_end(&mut predicted)
+            .unwrap();
+        let raw = unpredict(&predicted);
+
+        // 16 scanlines of 4 channels with 3 halfs each
+        assert_eq!(raw.len(), 16 * 4 * 3 * 2);
+        let alpha = f16::from_le_bytes([raw[0], raw[1]]);
+        let red = f16::from_le_bytes([raw[18], raw[19]]);
+        assert_eq!(alpha.to_f32(), 1.0);
+        assert_eq!(red.to_f32(), 0.0);
+        assert_eq!(f16::from_le_bytes([raw[42], raw[43]]).to_f32(), 3.0);
+    }
+}
@@ -2,7 +2,9 @@ use std::path::PathBuf;
 
 use clap::Parser;
 
-use crate::config::{Application, Config};
+use crate::config::{self, Application, Config};
+use crate::exr;
+use crate::output;
 
 /// Program that renders 3d objects
 #[derive(Parser, Debug)]
@@ -11,21 +13,70 @@ pub struct Flags {
     /// Config file to use
     #[arg(short, long)]
     pub config: PathBuf,
+
+    /// Amount of render threads, overrides `threads` in the config (defaults to all cores)
+    #[arg(short, long)]
+    pub threads: Option<usize>,
+
+    /// Seed for the random sampling, renders with the same seed are identical.
+    /// Overrides `seed` in the config (random if neither is set)
+    #[arg(short, long)]
+    pub seed: Option<u64>,
+
+    /// Image file to write, the format is picked from the extension (.png, .ppm, .pfm or .exr).
+    /// Without it an ASCII PPM is printed to stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Make the background transparent in formats with an alpha channel
+    #[arg(long)]
+    pub alpha: bool,
+
+    /// Precision of the channels in OpenEXR output
+    #[arg(long, value_enum, default_value_t)]
+    pub exr_type: exr::PixelType,
+
+    /// Compression of OpenEXR output
+    #[arg(long, value_enum, default_value_t)]
+    pub exr_compression: exr::Compression,
 }
 
 #[derive(Debug)]
 pub enum Error {
     FailedToReadFile(Box<dyn std::error::Error>),
     FailedToParse(ron::de::SpannedError),
+    InvalidConfig(config::Error),
 }
 
 impl Flags {
-    pub fn get_application(self) -> Result<Application, Error> {
-        let raw_config = std::fs::read_to_string(self.config)
+    pub fn output_options(&self) -> output::Options {
+        output::Options {
+            alpha: self.alpha,
+            exr_type: self.exr_type,
+            exr_compression: self.exr_compression,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_application(&self) -> Result<Application, Error> {
+        let raw_config = std::fs::read_to_string(&self.config)
             .map_err(|v| Error::FailedToReadFile(Box::new(v)))?;
 
-        Ok(ron::from_str::<Config>(&raw_config)
+        // Optional settings can be written without wrapping them in `Some(...)`
+        let mut app = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_str::<Config>(&raw_config)
             .map_err(Error::FailedToParse)?
-            .process())
+            .process()
+            .map_err(Error::InvalidConfig)?;
+
+        if let Some(threads) = self.threads {
+            app.threads = threads;
+        }
+        if let Some(seed) = self.seed {
+            app.seed = seed;
+        }
+
+        Ok(app)
     }
 }
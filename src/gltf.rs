@@ -0,0 +1,964 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bump::Bump;
+use crate::color::Color;
+use crate::image::{self, Image};
+use crate::material::Material;
+use crate::mesh::{self, MeshData};
+use crate::texture::{Filter, ImageFile, Texture, Wrap};
+use crate::transform::Mat4;
+use crate::vec3::Vec3;
+
+#[derive(Debug)]
+pub enum Error {
+    FailedToRead(PathBuf, std::io::Error),
+    InvalidJson(serde_json::Error),
+    InvalidData(&'static str),
+    Unsupported(String),
+    InvalidMesh(mesh::Error),
+    InvalidTexture(image::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::FailedToRead(path, e) => write!(f, "failed to read {}: {e}", path.display()),
+            Error::InvalidJson(e) => write!(f, "invalid glTF document: {e}"),
+            Error::InvalidData(reason) => write!(f, "invalid glTF data: {reason}"),
+            Error::Unsupported(feature) => write!(f, "unsupported glTF feature: {feature}"),
+            Error::InvalidMesh(e) => write!(f, "invalid glTF mesh: {e}"),
+            Error::InvalidTexture(e) => write!(f, "invalid glTF texture: {e}"),
+        }
+    }
+}
+
+// The parts of the glTF 2.0 document that are used, everything else is ignored
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    scene: Option<usize>,
+    #[serde(default)]
+    scenes: Vec<Scene>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    meshes: Vec<GltfMesh>,
+    #[serde(default)]
+    materials: Vec<GltfMaterial>,
+    #[serde(default)]
+    textures: Vec<GltfTexture>,
+    #[serde(default)]
+    images: Vec<GltfImage>,
+    #[serde(default)]
+    samplers: Vec<Sampler>,
+    #[serde(default)]
+    cameras: Vec<GltfCamera>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Scene {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    #[serde(default)]
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    camera: Option<usize>,
+    /// Column by column
+    matrix: Option<[f64; 16]>,
+    translation: Option<[f64; 3]>,
+    /// Quaternion as x, y, z and w
+    rotation: Option<[f64; 4]>,
+    scale: Option<[f64; 3]>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GltfMesh {
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    #[serde(default = "default_mode")]
+    mode: u32,
+}
+
+fn default_mode() -> u32 {
+    TRIANGLES
+}
+
+const TRIANGLES: u32 = 4;
+const TRIANGLE_STRIP: u32 = 5;
+const TRIANGLE_FAN: u32 = 6;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfMaterial {
+    #[serde(default)]
+    pbr_metallic_roughness: Pbr,
+    normal_texture: Option<NormalTextureInfo>,
+    emissive_texture: Option<TextureInfo>,
+    #[serde(default)]
+    emissive_factor: [f64; 3],
+    #[serde(default)]
+    extensions: MaterialExtensions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Pbr {
+    #[serde(default = "default_factor")]
+    base_color_factor: [f64; 4],
+    base_color_texture: Option<TextureInfo>,
+    #[serde(default = "default_one")]
+    metallic_factor: f64,
+    #[serde(default = "default_one")]
+    roughness_factor: f64,
+    metallic_roughness_texture: Option<TextureInfo>,
+}
+
+impl Default for Pbr {
+    fn default() -> Self {
+        Pbr {
+            base_color_factor: default_factor(),
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+        }
+    }
+}
+
+fn default_factor() -> [f64; 4] {
+    [1.0; 4]
+}
+
+fn default_one() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MaterialExtensions {
+    #[serde(rename = "KHR_materials_emissive_strength")]
+    emissive_strength: Option<EmissiveStrength>,
+    #[serde(rename = "KHR_materials_transmission")]
+    transmission: Option<Transmission>,
+    #[serde(rename = "KHR_materials_ior")]
+    ior: Option<Ior>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmissiveStrength {
+    #[serde(default = "default_one")]
+    emissive_strength: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Transmission {
+    #[serde(default)]
+    transmission_factor: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Ior {
+    #[serde(default = "default_ior")]
+    ior: f64,
+}
+
+fn default_ior() -> f64 {
+    1.5
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextureInfo {
+    index: usize,
+    #[serde(default)]
+    tex_coord: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NormalTextureInfo {
+    index: usize,
+    #[serde(default)]
+    tex_coord: usize,
+    #[serde(default = "default_one")]
+    scale: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GltfTexture {
+    source: Option<usize>,
+    sampler: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfImage {
+    uri: Option<String>,
+    buffer_view: Option<usize>,
+    mime_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Sampler {
+    mag_filter: Option<u32>,
+    #[serde(default = "default_wrap")]
+    wrap_s: u32,
+    #[serde(default = "default_wrap")]
+    wrap_t: u32,
+}
+
+fn default_wrap() -> u32 {
+    REPEAT
+}
+
+const NEAREST: u32 = 9728;
+const CLAMP_TO_EDGE: u32 = 33071;
+const MIRRORED_REPEAT: u32 = 33648;
+const REPEAT: u32 = 10497;
+
+#[derive(Debug, Deserialize)]
+struct GltfCamera {
+    perspective: Option<Perspective>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Perspective {
+    /// Vertical field of view in radians
+    yfov: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    sparse: Option<serde::de::IgnoredAny>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Buffer {
+    uri: Option<String>,
+}
+
+/// Triangles of one primitive, placed where the node hierarchy puts them
+#[derive(Debug)]
+pub struct Part {
+    pub data: Arc<MeshData>,
+    pub material: Material,
+}
+
+/// Perspective camera of the model
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    /// Places the camera, which looks down its -z axis with +y up
+    pub transform: Mat4,
+    /// Vertical field of view in degrees
+    pub vfov: f64,
+}
+
+/// glTF 2.0 model, either a `.gltf` document or a binary `.glb` file, loaded when the config is read
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "PathBuf", into = "PathBuf")]
+pub struct GltfFile {
+    path: PathBuf,
+    pub parts: Arc<Vec<Part>>,
+    /// First camera in the node hierarchy
+    pub camera: Option<Camera>,
+}
+
+impl TryFrom<PathBuf> for GltfFile {
+    type Error = Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        let data = std::fs::read(&path).map_err(|e| Error::FailedToRead(path.clone(), e))?;
+        let (parts, camera) = decode(&data, &path)?;
+
+        Ok(GltfFile {
+            path,
+            parts: Arc::new(parts),
+            camera,
+        })
+    }
+}
+
+impl From<GltfFile> for PathBuf {
+    fn from(file: GltfFile) -> Self {
+        file.path
+    }
+}
+
+/// Splits a binary `.glb` file into its JSON document and its binary buffer
+fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>), Error> {
+    const JSON: u32 = 0x4E4F534A;
+    const BIN: u32 = 0x004E4942;
+    let word = |pos: usize| {
+        data.get(pos..pos + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            .ok_or(Error::InvalidData("binary file ends early"))
+    };
+
+    if word(4)? != 2 {
+        return Err(Error::Unsupported(format!("binary glTF version {}", word(4)?)));
+    }
+
+    let (mut json, mut bin) = (None, None);
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let (length, kind) = (word(pos)? as usize, word(pos + 4)?);
+        let chunk = data
+            .get(pos + 8..pos + 8 + length)
+            .ok_or(Error::InvalidData("binary file ends early"))?;
+        match kind {
+            JSON => json = json.or(Some(chunk)),
+            BIN => bin = bin.or(Some(chunk)),
+            _ => {}
+        }
+        pos += 8 + length;
+    }
+
+    Ok((json.ok_or(Error::InvalidData("binary file without a JSON chunk"))?, bin))
+}
+
+fn base64(text: &str) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return Err(Error::InvalidData("invalid base64 data")),
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Contents of a `data:` URI with its media type, or the local file a relative URI points to
+enum Uri {
+    Data(Option<String>, Vec<u8>),
+    File(PathBuf),
+}
+
+fn resolve(uri: &str, dir: &Path) -> Result<Uri, Error> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (header, payload) = data.split_once(',').ok_or(Error::InvalidData("invalid data URI"))?;
+        let Some(media_type) = header.strip_suffix(";base64") else {
+            return Err(Error::Unsupported("data URIs that aren't base64".to_string()));
+        };
+        return Ok(Uri::Data(Some(media_type.to_string()), base64(payload)?));
+    }
+    if uri.contains("://") {
+        return Err(Error::Unsupported(format!("remote file {uri}")));
+    }
+
+    // Spaces and other special characters in file names are percent-encoded
+    let mut path = Vec::new();
+    let mut bytes = uri.bytes();
+    while let Some(b) = bytes.next() {
+        let hex = |b: Option<u8>| b.and_then(|b| (b as char).to_digit(16));
+        match b {
+            b'%' => match (hex(bytes.next()), hex(bytes.next())) {
+                (Some(high), Some(low)) => path.push((high * 16 + low) as u8),
+                _ => return Err(Error::InvalidData("invalid URI")),
+            },
+            _ => path.push(b),
+        }
+    }
+    let path = String::from_utf8(path).map_err(|_| Error::InvalidData("invalid URI"))?;
+
+    Ok(Uri::File(dir.join(path)))
+}
+
+/// Local transform of a node
+fn node_matrix(node: &Node) -> Mat4 {
+    if let Some(m) = node.matrix {
+        let mut matrix = Mat4::IDENTITY;
+        for (i, row) in matrix.0.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = m[4 * j + i];
+            }
+        }
+        return matrix;
+    }
+
+    let [tx, ty, tz] = node.translation.unwrap_or([0.0; 3]);
+    let [x, y, z, w] = node.rotation.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+    let [sx, sy, sz] = node.scale.unwrap_or([1.0; 3]);
+
+    Mat4::translation(Vec3::new(tx, ty, tz)) * Mat4::from_quaternion(x, y, z, w) * Mat4::scaling(Vec3::new(sx, sy, sz))
+}
+
+/// Multiplies the base colour by the vertex colours
+fn tint(material: &mut Material) {
+    match material {
+        Material::Principled { base_color, .. } => {
+            *base_color = Texture::Multiply(Box::new(Texture::VertexColor), Box::new(base_color.clone()));
+        }
+        Material::Bumpy { material, .. } => tint(material),
+        _ => {}
+    }
+}
+
+/// Most elements an accessor without a buffer view may have. Those take no room in the file, so
+/// nothing else limits their count
+const MAX_EMPTY_ELEMENTS: usize = 1 << 24;
+
+/// Reads the parts of a model, with its buffers and images found relative to `path`
+struct Loader<'a> {
+    document: Document,
+    buffers: Vec<Vec<u8>>,
+    path: &'a Path,
+    /// Materials by index, with the default material last
+    materials: Vec<Option<Material>>,
+    images: HashMap<usize, Option<ImageFile>>,
+}
+
+impl Loader<'_> {
+    fn buffer_view(&self, index: usize) -> Result<&[u8], Error> {
+        let view = self
+            .document
+            .buffer_views
+            .get(index)
+            .ok_or(Error::InvalidData("missing buffer view"))?;
+        self.buffers
+            .get(view.buffer)
+            .and_then(|buffer| buffer.get(view.byte_offset..view.byte_offset.checked_add(view.byte_length)?))
+            .ok_or(Error::InvalidData("buffer view outside of its buffer"))
+    }
+
+    /// Values of an accessor, as a flat list with `components` values per element
+    fn accessor(&self, index: usize, components: &[usize]) -> Result<(Vec<f64>, usize), Error> {
+        let accessor = self.document.accessors.get(index).ok_or(Error::InvalidData("missing accessor"))?;
+        if accessor.sparse.is_some() {
+            return Err(Error::Unsupported("sparse accessors".to_string()));
+        }
+
+        let count = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            kind => return Err(Error::Unsupported(format!("{kind} accessors"))),
+        };
+        if !components.contains(&count) {
+            return Err(Error::InvalidData("accessor has the wrong type"));
+        }
+
+        let (size, max): (usize, f64) = match accessor.component_type {
+            5120 => (1, 127.0),
+            5121 => (1, 255.0),
+            5122 => (2, 32767.0),
+            5123 => (2, 65535.0),
+            5125 => (4, u32::MAX as f64),
+            5126 => (4, 1.0),
+            _ => return Err(Error::InvalidData("unknown component type")),
+        };
+
+        // Accessors without a buffer view are all zeros
+        let Some(view_index) = accessor.buffer_view else {
+            if accessor.count > MAX_EMPTY_ELEMENTS {
+                return Err(Error::InvalidData("accessor without data is too large"));
+            }
+            return Ok((vec![0.0; accessor.count * count], count));
+        };
+        let view = self.buffer_view(view_index)?;
+        let stride = self.document.buffer_views[view_index].byte_stride.unwrap_or(count * size);
+
+        // The last element has to end within the view, before anything is read or allocated
+        let end = match accessor.count.checked_sub(1) {
+            Some(last) => last
+                .checked_mul(stride)
+                .and_then(|start| start.checked_add(accessor.byte_offset))
+                .and_then(|start| start.checked_add(count * size)),
+            None => Some(0),
+        };
+        if end.is_none_or(|end| end > view.len()) {
+            return Err(Error::InvalidData("accessor outside of its buffer view"));
+        }
+
+        let mut values = Vec::with_capacity(accessor.count * count);
+        for element in 0..accessor.count {
+            for component in 0..count {
+                let pos = accessor.byte_offset + element * stride + component * size;
+                let b = &view[pos..pos + size];
+                let value = match accessor.component_type {
+                    5120 => b[0] as i8 as f64,
+                    5121 => b[0] as f64,
+                    5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    5125 => u32::from_le_bytes(b.try_into().unwrap()) as f64,
+                    _ => f32::from_le_bytes(b.try_into().unwrap()) as f64,
+                };
+                values.push(if accessor.normalized { (value / max).max(-1.0) } else { value });
+            }
+        }
+
+        Ok((values, count))
+    }
+
+    fn vectors(&self, index: usize) -> Result<Vec<Vec3>, Error> {
+        let (values, _) = self.accessor(index, &[3])?;
+        Ok(values.chunks_exact(3).map(|v| Vec3::new(v[0], v[1], v[2])).collect())
+    }
+
+    fn primitive(&mut self, primitive: &Primitive) -> Result<Option<Part>, Error> {
+        let mode = primitive.mode;
+        if ![TRIANGLES, TRIANGLE_STRIP, TRIANGLE_FAN].contains(&mode) {
+            // Points and lines have no surface to render
+            return Ok(None);
+        }
+
+        let attribute = |name: &str| primitive.attributes.get(name).copied();
+        let vertices = self.vectors(attribute("POSITION").ok_or(Error::InvalidData("primitive without positions"))?)?;
+        let normals = attribute("NORMAL").map(|i| self.vectors(i)).transpose()?.unwrap_or_default();
+        // glTF puts the origin of textures at the top left, images here have it at the bottom left
+        let uvs = match attribute("TEXCOORD_0") {
+            Some(i) => self.accessor(i, &[2])?.0.chunks_exact(2).map(|uv| (uv[0], 1.0 - uv[1])).collect(),
+            None => Vec::new(),
+        };
+        let colors = match attribute("COLOR_0") {
+            Some(i) => {
+                let (values, count) = self.accessor(i, &[3, 4])?;
+                values.chunks_exact(count).map(|c| Color::new(c[0], c[1], c[2])).collect()
+            }
+            None => Vec::new(),
+        };
+
+        let indices: Vec<usize> = match primitive.indices {
+            Some(i) => self.accessor(i, &[1])?.0.iter().map(|&i| i as usize).collect(),
+            None => (0..vertices.len()).collect(),
+        };
+        let faces = match mode {
+            TRIANGLES => indices.chunks_exact(3).map(|f| [f[0], f[1], f[2]]).collect(),
+            // Every other triangle of a strip is turned around to keep them all counterclockwise
+            TRIANGLE_STRIP => (2..indices.len())
+                .map(|i| match i % 2 {
+                    0 => [indices[i - 2], indices[i - 1], indices[i]],
+                    _ => [indices[i - 1], indices[i - 2], indices[i]],
+                })
+                .collect(),
+            _ => (2..indices.len()).map(|i| [indices[0], indices[i - 1], indices[i]]).collect(),
+        };
+
+        let has_colors = !colors.is_empty();
+        let data = MeshData::new(vertices, normals, uvs, colors, faces).map_err(Error::InvalidMesh)?;
+        let mut material = self.material(primitive.material)?;
+        if has_colors {
+            tint(&mut material);
+        }
+
+        Ok(Some(Part {
+            data: Arc::new(data),
+            material,
+        }))
+    }
+
+    fn image(&mut self, index: usize) -> Result<Option<ImageFile>, Error> {
+        if let Some(image) = self.images.get(&index) {
+            return Ok(image.clone());
+        }
+
+        let image = self.document.images.get(index).ok_or(Error::InvalidData("missing image"))?;
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        let source = match (&image.uri, image.buffer_view) {
+            (Some(uri), _) => resolve(uri, dir)?,
+            (None, Some(view)) => Uri::Data(image.mime_type.clone(), self.buffer_view(view)?.to_vec()),
+            (None, None) => return Err(Error::InvalidData("image without data")),
+        };
+
+        let loaded = match source {
+            Uri::File(path) => ImageFile::read(path),
+            Uri::Data(media_type, data) => {
+                let extension = match media_type.as_deref() {
+                    Some("image/png") => "png",
+                    Some("image/jpeg") => "jpg",
+                    _ => "",
+                };
+                let name = PathBuf::from(format!("{}#image{index}.{extension}", self.path.display()));
+                Image::decode(&data, extension).and_then(|image| ImageFile::embedded(name, image))
+            }
+        };
+
+        // Images in formats that can't be read, like WebP from extensions, fall back to the plain colours
+        let file = match loaded {
+            Ok(file) => Some(file),
+            Err(image::Error::UnsupportedFormat(format)) => {
+                eprintln!("Skipping image {index} of {}: unsupported format {format:?}", self.path.display());
+                None
+            }
+            Err(e) => return Err(Error::InvalidTexture(e)),
+        };
+        self.images.insert(index, file.clone());
+
+        Ok(file)
+    }
+
+    /// Image texture, `None` when its image can't be read
+    fn texture(&mut self, index: usize, tex_coord: usize, srgb: bool) -> Result<Option<Texture>, Error> {
+        if tex_coord != 0 {
+            eprintln!("Skipping texture {index} of {}: only TEXCOORD_0 is supported", self.path.display());
+            return Ok(None);
+        }
+
+        let texture = self.document.textures.get(index).ok_or(Error::InvalidData("missing texture"))?;
+        let sampler = texture.sampler.and_then(|i| self.document.samplers.get(i));
+        let wrap = |mode| match mode {
+            CLAMP_TO_EDGE => Wrap::Clamp,
+            MIRRORED_REPEAT => Wrap::Mirror,
+            _ => Wrap::Repeat,
+        };
+        let (wrap_s, wrap_t) = sampler.map_or((REPEAT, REPEAT), |s| (s.wrap_s, s.wrap_t));
+        let filter = match sampler.and_then(|s| s.mag_filter) {
+            Some(NEAREST) => Filter::Nearest,
+            _ => Filter::Bilinear,
+        };
+
+        let Some(source) = texture.source else {
+            return Ok(None);
+        };
+        Ok(self.image(source)?.map(|path| Texture::Image {
+            path,
+            srgb: Some(srgb),
+            wrap: wrap(wrap_s),
+            wrap_v: Some(wrap(wrap_t)),
+            filter,
+            scale: (1.0, 1.0),
+            offset: (0.0, 0.0),
+        }))
+    }
+
+    /// `factor` times the texture, if there is one that can be read
+    fn factor_texture(&mut self, factor: Color, info: Option<&TextureInfo>) -> Result<Texture, Error> {
+        let texture = match info {
+            Some(info) => self.texture(info.index, info.tex_coord, true)?,
+            None => None,
+        };
+
+        Ok(match texture {
+            Some(texture) if factor == Color::new(1.0, 1.0, 1.0) => texture,
+            Some(texture) => Texture::Multiply(Box::new(factor.into()), Box::new(texture)),
+            None => factor.into(),
+        })
+    }
+
+    fn material(&mut self, index: Option<usize>) -> Result<Material, Error> {
+        // The default material of glTF is white and rough metal
+        let slot = index.unwrap_or(self.document.materials.len());
+        if let Some(Some(material)) = self.materials.get(slot) {
+            return Ok(material.clone());
+        }
+
+        let gltf = match index {
+            Some(i) => std::mem::take(self.document.materials.get_mut(i).ok_or(Error::InvalidData("missing material"))?),
+            None => GltfMaterial::default(),
+        };
+        let pbr = &gltf.pbr_metallic_roughness;
+        let [r, g, b, _] = pbr.base_color_factor;
+        let [er, eg, eb] = gltf.emissive_factor;
+
+        let metallic_roughness = match &pbr.metallic_roughness_texture {
+            Some(info) => self.texture(info.index, info.tex_coord, false)?,
+            None => None,
+        };
+        let mut material = Material::Principled {
+            base_color: self.factor_texture(Color::new(r, g, b), pbr.base_color_texture.as_ref())?,
+            metallic: pbr.metallic_factor,
+            roughness: pbr.roughness_factor,
+            metallic_roughness,
+            specular: 0.5,
+            transmission: gltf.extensions.transmission.as_ref().map_or(0.0, |t| t.transmission_factor),
+            ior: gltf.extensions.ior.as_ref().map_or(1.5, |i| i.ior),
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.0,
+            emission: self.factor_texture(Color::new(er, eg, eb), gltf.emissive_texture.as_ref())?,
+            emission_strength: gltf
+                .extensions
+                .emissive_strength
+                .as_ref()
+                .map_or(1.0, |e| e.emissive_strength),
+        };
+
+        if let Some(info) = &gltf.normal_texture {
+            if let Some(texture) = self.texture(info.index, info.tex_coord, false)? {
+                material = Material::Bumpy {
+                    material: Box::new(material),
+                    bump: Bump::Normal {
+                        texture,
+                        strength: info.scale,
+                    },
+                };
+            }
+        }
+
+        if self.materials.len() <= slot {
+            self.materials.resize(slot + 1, None);
+        }
+        self.materials[slot] = Some(material.clone());
+
+        Ok(material)
+    }
+
+    fn visit(&mut self, node: usize, parent: Mat4, depth: usize, parts: &mut Vec<Part>, camera: &mut Option<Camera>) -> Result<(), Error> {
+        if depth > self.document.nodes.len() {
+            return Err(Error::InvalidData("node hierarchy has a cycle"));
+        }
+        let (local, mesh, camera_index, children) = {
+            let node = self.document.nodes.get(node).ok_or(Error::InvalidData("missing node"))?;
+            (node_matrix(node), node.mesh, node.camera, node.children.clone())
+        };
+        let transform = parent * local;
+
+        if let Some(mesh) = mesh {
+            let primitives = std::mem::take(
+                &mut self
+                    .document
+                    .meshes
+                    .get_mut(mesh)
+                    .ok_or(Error::InvalidData("missing mesh"))?
+                    .primitives,
+            );
+            for primitive in &primitives {
+                if let Some(part) = self.primitive(primitive)? {
+                    parts.push(Part {
+                        data: Arc::new(part.data.transformed(&transform)),
+                        material: part.material,
+                    });
+                }
+            }
+            self.document.meshes[mesh].primitives = primitives;
+        }
+
+        if let Some(perspective) = camera_index.and_then(|i| self.document.cameras.get(i)?.perspective.as_ref()) {
+            camera.get_or_insert(Camera {
+                transform,
+                vfov: perspective.yfov.to_degrees(),
+            });
+        }
+
+        for child in children {
+            self.visit(child, transform, depth + 1, parts, camera)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes a `.gltf` or `.glb` file, reading the files it refers to relative to `path`
+pub fn decode(data: &[u8], path: &Path) -> Result<(Vec<Part>, Option<Camera>), Error> {
+    let (json, bin) = if data.starts_with(b"glTF") {
+        split_glb(data)?
+    } else {
+        (data, None)
+    };
+    let document: Document = serde_json::from_slice(json).map_err(Error::InvalidJson)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let buffers = document
+        .buffers
+        .iter()
+        .enumerate()
+        .map(|(i, buffer)| match (&buffer.uri, bin) {
+            (Some(uri), _) => match resolve(uri, dir)? {
+                Uri::Data(_, data) => Ok(data),
+                Uri::File(path) => std::fs::read(&path).map_err(|e| Error::FailedToRead(path, e)),
+            },
+            // Only the first buffer can be the one in the binary file
+            (None, Some(bin)) if i == 0 => Ok(bin.to_vec()),
+            (None, _) => Err(Error::InvalidData("buffer without data")),
+        })
+        .collect::<Result<_, _>>()?;
+
+    // Without a scene to show, every node that isn't a child of another one is shown
+    let roots = match document.scene.or((!document.scenes.is_empty()).then_some(0)) {
+        Some(scene) => document.scenes.get(scene).ok_or(Error::InvalidData("missing scene"))?.nodes.clone(),
+        None => {
+            let children: Vec<usize> = document.nodes.iter().flat_map(|n| n.children.iter().copied()).collect();
+            (0..document.nodes.len()).filter(|n| !children.contains(n)).collect()
+        }
+    };
+
+    let mut loader = Loader {
+        document,
+        buffers,
+        path,
+        materials: Vec::new(),
+        images: HashMap::new(),
+    };
+    let (mut parts, mut camera) = (Vec::new(), None);
+    for root in roots {
+        loader.visit(root, Mat4::IDENTITY, 0, &mut parts, &mut camera)?;
+    }
+
+    Ok((parts, camera))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A triangle moved by a parent and a child node, next to a camera turned to look down -x
+    const DOCUMENT: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [
+            { "translation": [0, 0, -2], "scale": [2, 2, 2], "children": [1, 2] },
+            { "mesh": 0, "matrix": [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 1, 0, 0, 1] },
+            { "camera": 0, "rotation": [0, 0.7071068, 0, 0.7071068] }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "TEXCOORD_0": 1 }, "material": 0 }] }],
+        "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1, 0.5, 0.25, 1], "metallicFactor": 0 } }],
+        "cameras": [{ "type": "perspective", "perspective": { "yfov": 0.7853982, "znear": 0.1 } }],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" },
+            { "bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": 3, "type": "VEC2" }
+        ],
+        "bufferViews": [{ "buffer": 0, "byteLength": 60 }],
+        "buffers": [BUFFER]
+    }"#;
+
+    const DATA: &str = "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAAAAAAAAAAAAIA/AAAAAAAAAAAAAIA/";
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-6
+    }
+
+    fn check(parts: &[Part], camera: Option<Camera>) {
+        assert_eq!(parts.len(), 1);
+        let data = &parts[0].data;
+        assert!(close(data.vertices[1], Vec3::new(4.0, 0.0, -2.0)));
+        assert!(close(data.vertices[2], Vec3::new(2.0, 2.0, -2.0)));
+        assert_eq!(data.uvs, [(0.0, 1.0), (1.0, 1.0), (0.0, 0.0)]);
+
+        let Material::Principled {
+            base_color: Texture::Solid(r, g, b),
+            metallic,
+            roughness,
+            ..
+        } = &parts[0].material
+        else {
+            panic!("{:?}", parts[0].material);
+        };
+        assert_eq!((*r, *g, *b, *metallic, *roughness), (1.0, 0.5, 0.25, 0.0, 1.0));
+
+        let camera = camera.unwrap();
+        assert!((camera.vfov - 45.0).abs() < 1e-4);
+        assert!(close(camera.transform.transform_point(&Vec3::default()), Vec3::new(0.0, 0.0, -2.0)));
+        assert!(close(
+            camera.transform.transform_vector(&Vec3::new(0.0, 0.0, -1.0)),
+            Vec3::new(-2.0, 0.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn test_gltf() {
+        let buffer = format!(r#"{{ "byteLength": 60, "uri": "data:application/octet-stream;base64,{DATA}" }}"#);
+        let document = DOCUMENT.replace("BUFFER", &buffer);
+        let (parts, camera) = decode(document.as_bytes(), Path::new("model.gltf")).unwrap();
+        check(&parts, camera);
+
+        let remote = DOCUMENT.replace("BUFFER", r#"{ "byteLength": 60, "uri": "https://example.com/data.bin" }"#);
+        assert!(matches!(decode(remote.as_bytes(), Path::new("")), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_accessor_bounds() {
+        let buffer = format!(r#"{{ "byteLength": 60, "uri": "data:application/octet-stream;base64,{DATA}" }}"#);
+        let document = DOCUMENT.replace("BUFFER", &buffer);
+        let broken = |from: &str, to: &str| {
+            let document = document.replace(from, to);
+            matches!(decode(document.as_bytes(), Path::new("")), Err(Error::InvalidData(_)))
+        };
+
+        // Counts reaching past the view, or past the end of memory, fail before anything is allocated
+        assert!(broken(r#""count": 3, "type": "VEC3""#, r#""count": 6, "type": "VEC3""#));
+        assert!(broken(r#""count": 3, "type": "VEC3""#, &format!(r#""count": {}, "type": "VEC3""#, usize::MAX)));
+        assert!(broken(r#""byteOffset": 36"#, &format!(r#""byteOffset": {}"#, usize::MAX)));
+        assert!(broken(
+            r#""bufferView": 0, "componentType": 5126, "count": 3"#,
+            &format!(r#""componentType": 5126, "count": {}"#, usize::MAX / 2)
+        ));
+    }
+
+    #[test]
+    fn test_glb() {
+        let mut json = DOCUMENT.replace("BUFFER", r#"{ "byteLength": 60 }"#).into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let bin = base64(DATA).unwrap();
+
+        let mut glb = b"glTF".to_vec();
+        glb.extend(2u32.to_le_bytes());
+        glb.extend(((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(b"JSON");
+        glb.extend(&json);
+        glb.extend((bin.len() as u32).to_le_bytes());
+        glb.extend(b"BIN\0");
+        glb.extend(&bin);
+
+        let (parts, camera) = decode(&glb, Path::new("model.glb")).unwrap();
+        check(&parts, camera);
+        assert!(decode(&glb[..40], Path::new("model.glb")).is_err());
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(base64("aGk").unwrap(), b"hi");
+        assert!(base64("a*b").is_err());
+    }
+}
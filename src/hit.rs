@@ -1,29 +1,99 @@
+use crate::aabb::Aabb;
+use crate::color::Color;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::sampling::Onb;
 use crate::Vec3;
 
 use std::fmt::Debug;
 
-#[derive(Default, Clone, Copy)]
-pub struct HitRecord {
+/// Distance new rays start away from the surface, relative to the size of the coordinates
+const RAY_OFFSET: f64 = 1e-9;
+
+#[derive(Clone, Copy)]
+pub struct HitRecord<'a> {
     pub t: f64,
     pub point: Vec3,
+    /// Outward normal used for shading, which bump and normal maps may tilt
     pub normal: Vec3,
+    /// Outward unit normal of the actual surface
+    pub geometric_normal: Vec3,
     pub u: f64,
     pub v: f64,
-    pub material: Material,
+    /// Change of the point along u and v, the tangents of the surface
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    /// Colour of the corners interpolated over the triangle, for meshes with vertex colours
+    pub color: Option<Color>,
+    pub material: &'a Material,
+}
+
+/// Stretch of a ray inside a solid, from the hit where it goes in to the one where it comes out.
+/// The normals of both point out of the solid
+#[derive(Clone, Copy)]
+pub struct Interval<'a> {
+    pub enter: HitRecord<'a>,
+    pub exit: HitRecord<'a>,
 }
 
-pub trait Hittable: Debug {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+pub trait Hittable: Debug + Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+    fn bounding_box(&self) -> Aabb;
+
+    /// Whether the object encloses a volume, so that `intervals` can tell where rays go in and out of it
+    fn is_solid(&self) -> bool {
+        false
+    }
+
+    /// Every stretch of the whole line of the ray, also behind its origin, that lies inside the
+    /// object, in order along the ray. `None` for objects that don't enclose a volume, whatever the ray
+    fn intervals(&self, _ray: &Ray) -> Option<Vec<Interval<'_>>> {
+        None
+    }
 }
 
-impl HitRecord {
-    pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
-        if Vec3::dot(&r.direction, outward_normal) > 0.0 {
-            self.normal = *outward_normal;
+impl HitRecord<'_> {
+    /// Unit shading normal on the side of the surface that the ray arrived from
+    pub fn facing_normal(&self, r: &Ray) -> Vec3 {
+        let normal = Vec3::unit_vector(&self.normal);
+
+        if Vec3::dot(&r.direction, &self.geometric_normal) > 0.0 {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    /// Ray leaving the hit point, starting just off the surface on the side it heads to so that it
+    /// can't hit the surface again because of rounding errors
+    pub fn spawn_ray(&self, direction: Vec3) -> Ray {
+        let p = self.point;
+        let offset = RAY_OFFSET * (1.0 + p.x().abs().max(p.y().abs()).max(p.z().abs()));
+
+        if Vec3::dot(&direction, &self.geometric_normal) > 0.0 {
+            Ray::new(p + offset * self.geometric_normal, direction)
         } else {
-            self.normal = *outward_normal * -1.0;
+            Ray::new(p - offset * self.geometric_normal, direction)
+        }
+    }
+
+    /// Unit tangent, bitangent and shading normal, with the tangent following u and the bitangent
+    /// on the same side as v
+    pub fn shading_frame(&self) -> (Vec3, Vec3, Vec3) {
+        let n = Vec3::unit_vector(&self.normal);
+        let along_u = self.dpdu - Vec3::dot(&self.dpdu, &n) * n;
+
+        let t = if along_u.length() > 1e-12 {
+            Vec3::unit_vector(&along_u)
+        } else {
+            Onb::from_w(&n).u
+        };
+        let b = Vec3::cross(&n, &t);
+
+        if Vec3::dot(&b, &self.dpdv) < 0.0 {
+            (t, -b, n)
+        } else {
+            (t, b, n)
         }
     }
 }
@@ -38,7 +108,7 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut hit_record = None;
         let mut closest_so_far = t_max;
 
@@ -51,6 +121,30 @@ impl Hittable for HittableList {
 
         hit_record
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.0
+            .iter()
+            .fold(Aabb::empty(), |aabb, object| aabb.union(&object.bounding_box()))
+    }
+}
+
+/// Hit at `point` with the shading and geometric normal `normal`, for tests. u and v are measured
+/// from the origin along the tangents
+#[cfg(test)]
+pub fn test_record(material: &Material, point: Vec3, normal: Vec3, (dpdu, dpdv): (Vec3, Vec3)) -> HitRecord<'_> {
+    HitRecord {
+        t: 1.0,
+        point,
+        normal,
+        geometric_normal: normal,
+        u: Vec3::dot(&point, &dpdu) / dpdu.squared_length(),
+        v: Vec3::dot(&point, &dpdv) / dpdv.squared_length(),
+        dpdu,
+        dpdv,
+        color: None,
+        material,
+    }
 }
 
 #[cfg(test)]
@@ -64,14 +158,57 @@ mod tests {
             t: 1.0,
             point: Vec3(1.0, 2.0, 3.0),
             normal: Vec3(0.0, 0.0, 1.0),
+            geometric_normal: Vec3(0.0, 0.0, 1.0),
             u: 1.0,
             v: 1.0,
-            material: Material::Lambertian {
-                albedo: Color::default(),
+            dpdu: Vec3(1.0, 0.0, 0.0),
+            dpdv: Vec3(0.0, 1.0, 0.0),
+            color: None,
+            material: &Material::Lambertian {
+                albedo: Color::default().into(),
             },
         };
         assert_eq!(hit_record.point, Vec3(1.0, 2.0, 3.0));
         assert_eq!(hit_record.t, 1.0);
         assert_eq!(hit_record.normal, Vec3(0.0, 0.0, 1.0));
     }
+
+    fn record(material: &Material) -> HitRecord<'_> {
+        test_record(material, Vec3(1e6, 0.1, -3.0), Vec3(0.0, 1.0, 0.0), (Vec3(2.0, 0.5, 0.0), Vec3(0.0, 0.0, -3.0)))
+    }
+
+    #[test]
+    fn test_spawn_ray() {
+        let material = Material::default();
+        let rec = record(&material);
+
+        // Rays start on the side of the surface they leave through, even far from the origin
+        let up = rec.spawn_ray(Vec3(0.3, 1.0, 0.0));
+        let down = rec.spawn_ray(Vec3(0.3, -1.0, 0.0));
+        assert!(up.origin.y() > 0.1 && down.origin.y() < 0.1);
+        assert!((up.origin - rec.point).length() < 1e-2);
+        assert_eq!(up.direction, Vec3(0.3, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_shading_frame() {
+        let material = Material::default();
+        let rec = record(&material);
+
+        // The tangent follows u within the surface and the bitangent ends up on the side of v
+        let (t, b, n) = rec.shading_frame();
+        assert!((t - Vec3(1.0, 0.0, 0.0)).length() < 1e-9);
+        assert!((b - Vec3(0.0, 0.0, -1.0)).length() < 1e-9);
+        assert_eq!(n, Vec3(0.0, 1.0, 0.0));
+
+        // Without tangents any frame around the normal will do
+        let rec = HitRecord {
+            dpdu: Vec3::default(),
+            dpdv: Vec3::default(),
+            ..rec
+        };
+        let (t, b, n) = rec.shading_frame();
+        assert!(Vec3::dot(&t, &n).abs() < 1e-9 && Vec3::dot(&b, &n).abs() < 1e-9);
+        assert!((Vec3::cross(&t, &b).length() - 1.0).abs() < 1e-9);
+    }
 }
@@ -0,0 +1,556 @@
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::color::Color;
+
+/// Floating point image, stored row by row from the top. Float formats are linear, 8 and 16-bit formats
+/// keep their encoded values scaled to 0.0-1.0
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    FailedToRead(std::io::Error),
+    UnsupportedFormat(String),
+    InvalidData(&'static str),
+    FailedToDecode(png::DecodingError),
+    FailedToDecodeJpeg(jpeg_decoder::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::FailedToRead(e) => write!(f, "failed to read image: {e}"),
+            Error::UnsupportedFormat(format) => write!(f, "unsupported image format: {format}"),
+            Error::InvalidData(reason) => write!(f, "invalid image data: {reason}"),
+            Error::FailedToDecode(e) => write!(f, "failed to decode image: {e}"),
+            Error::FailedToDecodeJpeg(e) => write!(f, "failed to decode image: {e}"),
+        }
+    }
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Image {
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Loads an image, picking the decoder from the file extension
+    pub fn load(path: &Path) -> Result<Image, Error> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let data = std::fs::read(path).map_err(Error::FailedToRead)?;
+
+        Image::decode(&data, &extension)
+    }
+
+    /// Decodes an image held in memory, in the format with the given file extension
+    pub fn decode(data: &[u8], extension: &str) -> Result<Image, Error> {
+        match extension {
+            "hdr" | "pic" => decode_hdr(data),
+            "pfm" => decode_pfm(data),
+            "ppm" => decode_ppm(data),
+            "png" => decode_png(data),
+            "jpg" | "jpeg" => decode_jpeg(data),
+            _ => Err(Error::UnsupportedFormat(extension.to_string())),
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Reads a whitespace separated header token, skipping `#` comments
+fn next_token<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a str, Error> {
+    loop {
+        while *pos < data.len() && data[*pos].is_ascii_whitespace() {
+            *pos += 1;
+        }
+        if *pos < data.len() && data[*pos] == b'#' {
+            while *pos < data.len() && data[*pos] != b'\n' {
+                *pos += 1;
+            }
+        } else {
+            break;
+        }
+    }
+
+    let start = *pos;
+    while *pos < data.len() && !data[*pos].is_ascii_whitespace() {
+        *pos += 1;
+    }
+
+    if start == *pos {
+        return Err(Error::InvalidData("unexpected end of header"));
+    }
+
+    std::str::from_utf8(&data[start..*pos]).map_err(|_| Error::InvalidData("header is not text"))
+}
+
+fn parse_token<T: std::str::FromStr>(data: &[u8], pos: &mut usize) -> Result<T, Error> {
+    next_token(data, pos)?
+        .parse()
+        .map_err(|_| Error::InvalidData("malformed header value"))
+}
+
+/// Number of pixels in an image of the size given by a header, which may be anything in a broken file
+fn pixel_count(width: usize, height: usize) -> Result<usize, Error> {
+    if width == 0 || height == 0 {
+        return Err(Error::InvalidData("image has no pixels"));
+    }
+
+    width
+        .checked_mul(height)
+        .ok_or(Error::InvalidData("image is too large"))
+}
+
+/// The `size` bytes from `pos` on, or an error when the data ends before them
+fn raster(data: &[u8], pos: usize, size: Option<usize>) -> Result<&[u8], Error> {
+    size.and_then(|size| data.get(pos..pos.checked_add(size)?))
+        .ok_or(Error::InvalidData("raster is shorter than the header says"))
+}
+
+/// Portable Float Map, either RGB (`PF`) or greyscale (`Pf`)
+fn decode_pfm(data: &[u8]) -> Result<Image, Error> {
+    let mut pos = 0;
+    let channels = match next_token(data, &mut pos)? {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(Error::InvalidData("missing PFM magic number")),
+    };
+    let width: usize = parse_token(data, &mut pos)?;
+    let height: usize = parse_token(data, &mut pos)?;
+    let scale: f64 = parse_token(data, &mut pos)?;
+    pos += 1; // single whitespace character before the raster
+
+    let size = pixel_count(width, height)?.checked_mul(channels * 4);
+
+    let values: Vec<f64> = raster(data, pos, size)?
+        .chunks_exact(4)
+        .map(|b| {
+            let bytes = [b[0], b[1], b[2], b[3]];
+            // A negative scale marks little endian data
+            if scale < 0.0 {
+                f32::from_le_bytes(bytes) as f64
+            } else {
+                f32::from_be_bytes(bytes) as f64
+            }
+        })
+        .collect();
+
+    // PFM rows go from the bottom of the image to the top
+    let pixels = values
+        .chunks_exact(width * channels)
+        .rev()
+        .flat_map(|row| {
+            row.chunks_exact(channels).map(|p| match p {
+                [r, g, b] => Color::new(*r, *g, *b),
+                _ => Color::new(p[0], p[0], p[0]),
+            })
+        })
+        .collect();
+
+    Ok(Image::new(width, height, pixels))
+}
+
+/// Binary (`P6`) or ASCII (`P3`) PPM with 8 or 16 bits per channel
+fn decode_ppm(data: &[u8]) -> Result<Image, Error> {
+    let mut pos = 0;
+    let binary = match next_token(data, &mut pos)? {
+        "P6" => true,
+        "P3" => false,
+        _ => return Err(Error::InvalidData("missing PPM magic number")),
+    };
+    let width: usize = parse_token(data, &mut pos)?;
+    let height: usize = parse_token(data, &mut pos)?;
+    let max_value: u16 = parse_token(data, &mut pos)?;
+    if max_value == 0 {
+        return Err(Error::InvalidData("PPM max value is zero"));
+    }
+
+    let count = pixel_count(width, height)?
+        .checked_mul(3)
+        .ok_or(Error::InvalidData("image is too large"))?;
+    let values: Vec<f64> = if binary {
+        pos += 1; // single whitespace character before the raster
+        let size = if max_value > 255 { 2 } else { 1 };
+
+        raster(data, pos, count.checked_mul(size))?
+            .chunks_exact(size)
+            .map(|b| match b {
+                [high, low] => u16::from_be_bytes([*high, *low]) as f64,
+                _ => b[0] as f64,
+            })
+            .collect()
+    } else {
+        (0..count)
+            .map(|_| parse_token::<u16>(data, &mut pos).map(f64::from))
+            .collect::<Result<_, _>>()?
+    };
+
+    let pixels = values
+        .chunks_exact(3)
+        .map(|p| Color::new(p[0], p[1], p[2]) / max_value as f64)
+        .collect();
+
+    Ok(Image::new(width, height, pixels))
+}
+
+/// PNG of any colour type, palettes and low bit depths are expanded and alpha is dropped
+fn decode_png(data: &[u8]) -> Result<Image, Error> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(Error::FailedToDecode)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(Error::FailedToDecode)?;
+
+    let (max_value, size) = match info.bit_depth {
+        png::BitDepth::Sixteen => (65535.0, 2),
+        _ => (255.0, 1),
+    };
+    let channels = info.color_type.samples();
+
+    let pixels = buffer[..info.buffer_size()]
+        .chunks_exact(info.line_size)
+        .flat_map(|line| {
+            line[..info.width as usize * channels * size]
+                .chunks_exact(channels * size)
+                .map(|p| {
+                    let value = |i: usize| match size {
+                        2 => u16::from_be_bytes([p[2 * i], p[2 * i + 1]]) as f64 / max_value,
+                        _ => p[i] as f64 / max_value,
+                    };
+
+                    // Greyscale images have one or two channels, colour images three or four
+                    if channels < 3 {
+                        Color::new(value(0), value(0), value(0))
+                    } else {
+                        Color::new(value(0), value(1), value(2))
+                    }
+                })
+        })
+        .collect();
+
+    Ok(Image::new(info.width as usize, info.height as usize, pixels))
+}
+
+/// Baseline or progressive JPEG in greyscale or RGB
+fn decode_jpeg(data: &[u8]) -> Result<Image, Error> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let buffer = decoder.decode().map_err(Error::FailedToDecodeJpeg)?;
+    let info = decoder.info().ok_or(Error::InvalidData("missing JPEG frame"))?;
+
+    let pixels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => buffer
+            .iter()
+            .map(|&l| {
+                let l = l as f64 / 255.0;
+                Color::new(l, l, l)
+            })
+            .collect(),
+        jpeg_decoder::PixelFormat::L16 => buffer
+            .chunks_exact(2)
+            .map(|b| {
+                let l = u16::from_ne_bytes([b[0], b[1]]) as f64 / 65535.0;
+                Color::new(l, l, l)
+            })
+            .collect(),
+        jpeg_decoder::PixelFormat::RGB24 => buffer
+            .chunks_exact(3)
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64) / 255.0)
+            .collect(),
+        jpeg_decoder::PixelFormat::CMYK32 => return Err(Error::UnsupportedFormat("CMYK JPEG".to_string())),
+    };
+
+    Ok(Image::new(info.width as usize, info.height as usize, pixels))
+}
+
+fn rgbe_to_color(rgbe: [u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::default();
+    }
+
+    let f = 2f64.powi(rgbe[3] as i32 - (128 + 8));
+    Color::new(
+        (rgbe[0] as f64 + 0.5) * f,
+        (rgbe[1] as f64 + 0.5) * f,
+        (rgbe[2] as f64 + 0.5) * f,
+    )
+}
+
+/// Radiance RGBE image, flat or run length encoded
+fn decode_hdr(data: &[u8]) -> Result<Image, Error> {
+    if !data.starts_with(b"#?") {
+        return Err(Error::InvalidData("missing Radiance signature"));
+    }
+
+    // Header lines end with an empty line, followed by the resolution line
+    let mut pos = 0;
+    loop {
+        let end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or(Error::InvalidData("unterminated header"))?;
+        let line = &data[pos..pos + end];
+        pos += end + 1;
+
+        if line.starts_with(b"FORMAT=") && line != b"FORMAT=32-bit_rle_rgbe" {
+            return Err(Error::UnsupportedFormat(
+                String::from_utf8_lossy(&line[7..]).into_owned(),
+            ));
+        }
+        if line.is_empty() {
+            break;
+        }
+    }
+
+    let y_axis = next_token(data, &mut pos)?;
+    let height: usize = parse_token(data, &mut pos)?;
+    let x_axis = next_token(data, &mut pos)?;
+    let width: usize = parse_token(data, &mut pos)?;
+    pos += 1;
+
+    if x_axis != "+X" || (y_axis != "-Y" && y_axis != "+Y") {
+        return Err(Error::UnsupportedFormat(format!("{y_axis} {x_axis} orientation")));
+    }
+
+    // Runs let a small file claim a huge image, so only as much is reserved as the file could hold
+    let count = pixel_count(width, height)?;
+    let mut pixels = Vec::with_capacity(count.min(data.len()));
+    let mut scanline = vec![[0u8; 4]; width];
+
+    for _ in 0..height {
+        read_hdr_scanline(data, &mut pos, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_color(rgbe)));
+    }
+
+    // `+Y` images are stored from the bottom up
+    if y_axis == "+Y" {
+        let rows: Vec<Color> = pixels.chunks_exact(width).rev().flatten().copied().collect();
+        pixels = rows;
+    }
+
+    Ok(Image::new(width, height, pixels))
+}
+
+fn read_hdr_scanline(data: &[u8], pos: &mut usize, scanline: &mut [[u8; 4]]) -> Result<(), Error> {
+    let mut byte = || {
+        let b = data.get(*pos).copied().ok_or(Error::InvalidData("truncated scanline"));
+        *pos += 1;
+        b
+    };
+    let width = scanline.len();
+    if width == 0 {
+        return Ok(());
+    }
+
+    let first = [byte()?, byte()?, byte()?, byte()?];
+    let is_rle = (8..0x8000).contains(&width)
+        && first[0] == 2
+        && first[1] == 2
+        && ((first[2] as usize) << 8 | first[3] as usize) == width;
+
+    if is_rle {
+        // Every channel is run length encoded separately
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = byte()? as usize;
+                if count > 128 {
+                    let count = count - 128;
+                    let value = byte()?;
+                    if x + count > width {
+                        return Err(Error::InvalidData("run exceeds scanline"));
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                } else {
+                    if count == 0 || x + count > width {
+                        return Err(Error::InvalidData("invalid run length"));
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = byte()?;
+                    }
+                    x += count;
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Flat pixels, possibly using the old style (1, 1, 1, n) repeat markers
+    let mut x: usize = 0;
+    let mut shift = 0;
+    let mut rgbe = first;
+    loop {
+        if rgbe[0] == 1 && rgbe[1] == 1 && rgbe[2] == 1 {
+            // Every marker in a row adds the next 8 bits of the count
+            if shift > 24 {
+                return Err(Error::InvalidData("too many repeat markers in a row"));
+            }
+            let count = (rgbe[3] as usize) << shift;
+            let previous = *scanline
+                .get(x.wrapping_sub(1))
+                .ok_or(Error::InvalidData("repeat marker at start of scanline"))?;
+            if x + count > width {
+                return Err(Error::InvalidData("run exceeds scanline"));
+            }
+            scanline[x..x + count].fill(previous);
+            x += count;
+            shift += 8;
+        } else {
+            scanline[x] = rgbe;
+            x += 1;
+            shift = 0;
+        }
+
+        if x >= width {
+            return Ok(());
+        }
+        rgbe = [byte()?, byte()?, byte()?, byte()?];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_pfm() {
+        let mut data = b"PF\n2 1\n-1.0\n".to_vec();
+        for v in [1.0f32, 2.0, 3.0, 0.5, 0.25, 0.125] {
+            data.extend(v.to_le_bytes());
+        }
+
+        let image = decode_pfm(&data).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.get(0, 0), Color::new(1.0, 2.0, 3.0));
+        assert_eq!(image.get(1, 0), Color::new(0.5, 0.25, 0.125));
+    }
+
+    #[test]
+    fn test_decode_pfm_bad_size() {
+        assert!(matches!(decode_pfm(b"PF\n0 5\n-1\n"), Err(Error::InvalidData(_))));
+        let huge = format!("PF\n{} {}\n-1\n", usize::MAX / 2, 3);
+        assert!(matches!(decode_pfm(huge.as_bytes()), Err(Error::InvalidData(_))));
+    }
+
+    #[test]
+    fn test_decode_hdr_rle() {
+        let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+        data.extend([2, 2, 0, 8]);
+        // Red: run of 8, green: 8 literals, blue: run of 8, exponent: run of 8
+        data.extend([128 + 8, 255]);
+        data.extend([8, 0, 1, 2, 3, 4, 5, 6, 7]);
+        data.extend([128 + 8, 0]);
+        data.extend([128 + 8, 129]);
+
+        let image = decode_hdr(&data).unwrap();
+        assert_eq!((image.width, image.height), (8, 1));
+        assert_eq!(image.get(3, 0), Color::new(255.5 / 128.0, 3.5 / 128.0, 0.5 / 128.0));
+    }
+
+    #[test]
+    fn test_decode_hdr_flat() {
+        let mut data = b"#?RGBE\n\n-Y 2 +X 1\n".to_vec();
+        data.extend([128, 64, 0, 128]);
+        data.extend([0, 0, 0, 0]);
+
+        let image = decode_hdr(&data).unwrap();
+        assert_eq!(image.get(0, 0), Color::new(128.5 / 256.0, 64.5 / 256.0, 0.5 / 256.0));
+        assert_eq!(image.get(0, 1), Color::default());
+
+        assert!(matches!(decode_hdr(b"#?RGBE\n\n+Y 0 +X 1\n"), Err(Error::InvalidData(_))));
+    }
+
+    #[test]
+    fn test_decode_hdr_repeats() {
+        let mut data = b"#?RGBE\n\n-Y 1 +X 6\n".to_vec();
+        data.extend([10, 20, 30, 128]);
+        data.extend([1, 1, 1, 5]);
+
+        let image = decode_hdr(&data).unwrap();
+        assert_eq!(image.get(5, 0), image.get(0, 0));
+
+        // Long chains of markers would shift the count past the size of an integer
+        data.truncate(data.len() - 4);
+        for _ in 0..9 {
+            data.extend([1, 1, 1, 0]);
+        }
+        assert!(matches!(decode_hdr(&data), Err(Error::InvalidData(_))));
+    }
+
+    #[test]
+    fn test_decode_ppm() {
+        let binary = decode_ppm(b"P6\n# comment\n2 1\n255\n\xff\x00\x80\x00\x33\xff").unwrap();
+        assert_eq!((binary.width, binary.height), (2, 1));
+        assert_eq!(binary.get(0, 0), Color::new(1.0, 0.0, 128.0 / 255.0));
+        assert_eq!(binary.get(1, 0), Color::new(0.0, 0.2, 1.0));
+
+        let ascii = decode_ppm(b"P3 1 2 15\n15 0 3\n0 0 15\n").unwrap();
+        assert_eq!(ascii.get(0, 0), Color::new(1.0, 0.0, 0.2));
+        assert_eq!(ascii.get(0, 1), Color::new(0.0, 0.0, 1.0));
+
+        let wide = decode_ppm(b"P6 1 1 65535\n\xff\xff\x00\x00\x80\x00").unwrap();
+        assert_eq!(wide.get(0, 0), Color::new(1.0, 0.0, 32768.0 / 65535.0));
+
+        assert!(decode_ppm(b"P6 2 2 255\n\x00").is_err());
+    }
+
+    #[test]
+    fn test_decode_jpeg() {
+        // Red on the left half and blue on the right, which survive the compression roughly
+        let image = Image::load(Path::new("tests/scenes/swatch.jpg")).unwrap();
+        assert_eq!((image.width, image.height), (16, 8));
+        assert!((image.get(2, 4) - Color::new(200.0, 40.0, 40.0) / 255.0).length() < 0.05);
+        assert!((image.get(13, 4) - Color::new(40.0, 60.0, 200.0) / 255.0).length() < 0.05);
+
+        assert!(matches!(decode_jpeg(b"\xff\xd8\xff"), Err(Error::FailedToDecodeJpeg(_))));
+    }
+
+    fn encode_png(width: u32, height: u32, color: png::ColorType, depth: png::BitDepth, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+
+        out
+    }
+
+    #[test]
+    fn test_decode_png() {
+        let rgba = encode_png(2, 1, png::ColorType::Rgba, png::BitDepth::Eight, &[255, 0, 51, 0, 0, 255, 0, 255]);
+        let image = decode_png(&rgba).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.get(0, 0), Color::new(1.0, 0.0, 0.2));
+        assert_eq!(image.get(1, 0), Color::new(0.0, 1.0, 0.0));
+
+        let grey = encode_png(1, 2, png::ColorType::Grayscale, png::BitDepth::Sixteen, &[255, 255, 0, 0]);
+        let image = decode_png(&grey).unwrap();
+        assert_eq!(image.get(0, 0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(image.get(0, 1), Color::default());
+
+        // Two pixels per byte
+        let low = encode_png(2, 1, png::ColorType::Grayscale, png::BitDepth::Four, &[0xf0]);
+        let image = decode_png(&low).unwrap();
+        assert_eq!(image.get(0, 0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(image.get(1, 0), Color::default());
+    }
+}
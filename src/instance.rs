@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::hit::*;
+use crate::ray::Ray;
+use crate::transform::Mat4;
+use crate::vec3::Vec3;
+
+/// Object placed by a transform, sharing the object with the other instances of it. Rays are moved
+/// into the space of the object and the hits are moved back out
+#[derive(Debug)]
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    matrix: Mat4,
+    inverse: Mat4,
+    /// Inverse transpose, which keeps normals perpendicular to scaled surfaces
+    normal_matrix: Mat4,
+    bbox: Aabb,
+}
+
+impl Instance {
+    /// `None` when the transform squashes the object flat
+    pub fn new(object: Arc<dyn Hittable>, matrix: Mat4) -> Option<Instance> {
+        let inverse = matrix.inverse()?;
+
+        // The box around the corners of the moved box of the object
+        let inner = object.bounding_box();
+        let bbox = if inner.min.x() > inner.max.x() {
+            inner
+        } else {
+            (0..8).fold(Aabb::empty(), |bbox, corner| {
+                let pick = |axis: usize| match corner >> axis & 1 {
+                    0 => inner.min[axis],
+                    _ => inner.max[axis],
+                };
+                bbox.grow(&matrix.transform_point(&Vec3::new(pick(0), pick(1), pick(2))))
+            })
+        };
+
+        Some(Instance {
+            object,
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+            bbox,
+        })
+    }
+}
+
+impl Instance {
+    /// The ray in the space of the object, where it gets a unit direction. Distances along it are
+    /// longer by the returned scale
+    fn local_ray(&self, r: &Ray) -> (Ray, f64) {
+        let direction = self.inverse.transform_vector(&r.direction);
+        let scale = direction.length();
+
+        (Ray::new(self.inverse.transform_point(&r.origin), direction / scale), scale)
+    }
+
+    fn to_world<'a>(&self, rec: HitRecord<'a>, scale: f64) -> HitRecord<'a> {
+        HitRecord {
+            t: rec.t / scale,
+            point: self.matrix.transform_point(&rec.point),
+            normal: self.normal_matrix.transform_vector(&rec.normal).unit(),
+            geometric_normal: self.normal_matrix.transform_vector(&rec.geometric_normal).unit(),
+            dpdu: self.matrix.transform_vector(&rec.dpdu),
+            dpdv: self.matrix.transform_vector(&rec.dpdv),
+            ..rec
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (local, scale) = self.local_ray(r);
+        let rec = self.object.hit(&local, t_min * scale, t_max * scale)?;
+
+        Some(self.to_world(rec, scale))
+    }
+
+    fn is_solid(&self) -> bool {
+        self.object.is_solid()
+    }
+
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval<'_>>> {
+        let (local, scale) = self.local_ray(r);
+        let intervals = self.object.intervals(&local)?;
+
+        Some(
+            intervals
+                .into_iter()
+                .map(|i| Interval {
+                    enter: self.to_world(i.enter, scale),
+                    exit: self.to_world(i.exit, scale),
+                })
+                .collect(),
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::Cube;
+    use crate::material::Material;
+    use crate::transform::{Rotation, Transform};
+
+    #[test]
+    fn test_instance() {
+        let cube = Arc::new(Cube::new(
+            Vec3::new(-0.5, -0.5, -0.5),
+            Vec3::new(0.5, 0.5, 0.5),
+            Material::default(),
+        ));
+        let transform = Transform {
+            translate: Vec3::new(0.0, 0.0, -5.0),
+            rotate: Rotation::Euler(0.0, 45.0, 0.0),
+            scale: Vec3::new(2.0, 1.0, 1.0),
+        };
+        let instance = Instance::new(cube, transform.matrix()).unwrap();
+
+        // The stretched cube is turned so that its +z face looks towards +x and +z
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0));
+        let rec = instance.hit(&ray, 0.0, f64::MAX).unwrap();
+        assert!((rec.point.z() - (-5.0 + std::f64::consts::FRAC_1_SQRT_2)).abs() < 1e-9);
+        assert!((ray.at(rec.t) - rec.point).length() < 1e-9);
+        assert!((rec.geometric_normal.length() - 1.0).abs() < 1e-9);
+        assert!((rec.geometric_normal - Vec3::new(1.0, 0.0, 1.0).unit()).length() < 1e-9);
+
+        // Stretched along x, the cube reaches rays that would pass the original
+        let side = Ray::new(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(instance.hit(&side, 0.0, f64::MAX).is_some());
+        assert!(instance.hit(&Ray::new(Vec3::new(0.0, 0.6, 0.0), side.direction), 0.0, f64::MAX).is_none());
+        assert!(instance.hit(&ray, 0.0, 1.0).is_none());
+
+        let bbox = instance.bounding_box();
+        assert!((bbox.max.x() - 1.5 / std::f64::consts::SQRT_2).abs() < 1e-9);
+        assert!((bbox.max.y() - 0.5).abs() < 1e-9);
+        assert!(Instance::new(instance.object.clone(), Mat4::scaling(Vec3::default())).is_none());
+    }
+}
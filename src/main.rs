@@ -1,45 +1,44 @@
-#![feature(let_chains)]
-
+pub mod aabb;
+pub mod background;
+pub mod bsdf;
+pub mod bump;
+pub mod bvh;
 pub mod camera;
 pub mod color;
 pub mod config;
+pub mod csg;
 pub mod cube;
+pub mod environment;
+pub mod exr;
 pub mod flags;
+pub mod gltf;
 pub mod hit;
+pub mod image;
+pub mod instance;
 pub mod material;
+pub mod mesh;
+pub mod noise;
+pub mod obj;
+pub mod output;
 pub mod plane_surf;
+pub mod ply;
 mod cylinder;
 pub mod ray;
+pub mod render;
+pub mod sampling;
 pub mod sphere;
+pub mod stl;
+pub mod texture;
+pub mod tone_map;
+pub mod transform;
+pub mod triangle;
 pub mod vec3;
 
 use clap::Parser;
-use color::Color;
-use hit::{Hittable, HittableList};
-use material::scatter;
-use rand::prelude::*;
-use ray::Ray;
 use vec3::Vec3;
 
 use crate::flags::Flags;
 
-fn color(r: &Ray, world: &HittableList, depth: i32) -> Color {
-    if let Some(rec) = world.hit(r, 0.0, std::f64::MAX) {
-        if depth < 50 && let Some((attenuation, scattered)) = scatter(&rec.material, r, &rec) {
-            attenuation * color(&scattered, world, depth + 1)
-        } else {
-            Color::new(0.0, 0.0, 0.0)
-        }
-    } else {
-        let unit_direction = Vec3::unit_vector(&r.direction);
-        let t = 0.5 * (unit_direction.y() + 1.0);
-
-        Color::new(1.0, 1.0, 1.0) * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t
-    }
-}
-
-const MAX_RGB_VALUE: u8 = 255; // Max value in RGB colours (0...255)
-
 fn main() {
     let flags = Flags::parse();
     if !flags.config.exists() || !flags.config.is_file() {
@@ -47,45 +46,34 @@ fn main() {
         return;
     }
 
-    let app = flags.get_application().expect("Failed to parse config");
-
-    let mut rng = rand::thread_rng();
-    let brightness = if app.light > 0 && app.light <= 100 {
-        app.light as f64 / 100.0
-    } else {
-        1.0
-    };
-
-    let debug_pad = app.height.to_string().len();
-
-    println!("P3\n{} {}\n{MAX_RGB_VALUE}", app.width, app.height);
-
-    for j in (0..app.height).rev() {
-        eprint!("\rScanlines remaining: {j: <debug_pad$}");
-
-        for i in 0..app.width {
-            let mut col: Color = (0..app.samples)
-                .map(|_| {
-                    let u = (i as f64 + rng.gen::<f64>()) / app.width as f64;
-                    let v = (j as f64 + rng.gen::<f64>()) / app.height as f64;
-                    let r = &app.camera.get_ray(u, v);
-
-                    color(r, &app.world, 1)
-                })
-                .sum();
+    if let Some(Err(e)) = flags.output.as_deref().map(output::Format::from_path) {
+        eprintln!("{e}");
+        return;
+    }
 
-            col /= app.samples as f64;
-            col = brightness * col;
+    let app = flags.get_application().expect("Failed to parse config");
 
-            let adjust = |f: f64| (255.99 * f) as i32;
+    let mut framebuffer = render::render(&app);
+    let exposure = 2f64.powf(app.exposure);
+    for pixel in framebuffer.pixels.iter_mut().chain(&mut framebuffer.background) {
+        *pixel = exposure * *pixel;
+    }
 
-            let ir = adjust(col.r());
-            let ig = adjust(col.g());
-            let ib = adjust(col.b());
+    let options = output::Options {
+        tone_map: app.tone_map,
+        ..flags.output_options()
+    };
 
-            println!("{ir} {ig} {ib}");
+    match &flags.output {
+        Some(path) => output::write(path, &framebuffer, &options),
+        None => {
+            if options.alpha {
+                eprintln!("Ppm has no alpha channel, writing an opaque image to stdout");
+            }
+            output::write_ppm_ascii(&mut std::io::stdout().lock(), &framebuffer, app.tone_map)
         }
     }
+    .expect("Failed to write image");
 
     eprintln!("\nDone!");
 }
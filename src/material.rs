@@ -1,37 +1,178 @@
+use crate::bsdf::{Bsdf, ComplexIor, MicrofacetReflection, Principled, RoughDielectric};
+use crate::bump::Bump;
 use crate::color::Color;
 use crate::hit::HitRecord;
 use crate::ray::Ray;
+use crate::sampling::{cosine_weighted, random_in_unit_sphere, Onb};
+use crate::texture::{Images, LoadError, Texture};
 use crate::vec3::Vec3;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Material {
-    Lambertian { albedo: Color },
-    Metal { albedo: Color },
+    Lambertian { albedo: Texture },
+    Metal {
+        albedo: Texture,
+        /// Radius of the random offset added to reflections, 0.0 is a perfect mirror and 1.0 very rough
+        #[serde(default)]
+        fuzz: f64,
+    },
     Dielectric { ref_idx: f64 },
+    /// Physically based metal with GGX microfacets
+    Conductor {
+        ior: ComplexIor,
+        #[serde(default)]
+        roughness: f64,
+    },
+    /// Physically based glass with GGX microfacets, refracting and reflecting
+    RoughDielectric {
+        ior: f64,
+        #[serde(default)]
+        roughness: f64,
+    },
+    DiffuseLight {
+        emit: Texture,
+        #[serde(default = "default_strength")]
+        strength: f64,
+    },
+    /// Single tunable material covering everything from plastic and metal to glass, every field is optional
+    Principled {
+        #[serde(default = "default_base_color")]
+        base_color: Texture,
+        #[serde(default)]
+        metallic: f64,
+        #[serde(default = "default_roughness")]
+        roughness: f64,
+        /// Scales `metallic` by the blue channel and `roughness` by the green one, as in glTF models
+        #[serde(default)]
+        metallic_roughness: Option<Texture>,
+        #[serde(default = "default_specular")]
+        specular: f64,
+        #[serde(default)]
+        transmission: f64,
+        #[serde(default = "default_ior")]
+        ior: f64,
+        #[serde(default)]
+        clearcoat: f64,
+        #[serde(default = "default_clearcoat_roughness")]
+        clearcoat_roughness: f64,
+        #[serde(default)]
+        sheen: f64,
+        #[serde(default)]
+        emission: Texture,
+        #[serde(default = "default_strength")]
+        emission_strength: f64,
+    },
+    /// Another material with a bump or normal map, which changes how light reflects off the surface
+    /// but not its shape
+    Bumpy { material: Box<Material>, bump: Bump },
+}
+
+fn default_strength() -> f64 {
+    1.0
+}
+
+fn default_base_color() -> Texture {
+    Texture::Solid(0.8, 0.8, 0.8)
+}
+
+fn default_roughness() -> f64 {
+    0.5
+}
+
+fn default_specular() -> f64 {
+    0.5
+}
+
+fn default_ior() -> f64 {
+    1.5
+}
+
+fn default_clearcoat_roughness() -> f64 {
+    0.03
 }
 
 impl Default for Material {
     fn default() -> Self {
         Material::Lambertian {
-            albedo: Color::default(),
+            albedo: Texture::default(),
         }
     }
 }
 
-pub fn scatter(material: &Material, ray_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+impl Material {
+    /// Loads the images of the textures of the material, sharing the ones in `images`
+    pub fn load(&mut self, images: &Images) -> Result<(), LoadError> {
+        match self {
+            Material::Lambertian { albedo } | Material::Metal { albedo, .. } => albedo.load(images),
+            Material::DiffuseLight { emit, .. } => emit.load(images),
+            Material::Principled {
+                base_color,
+                metallic_roughness,
+                emission,
+                ..
+            } => {
+                base_color.load(images)?;
+                if let Some(texture) = metallic_roughness {
+                    texture.load(images)?;
+                }
+                emission.load(images)
+            }
+            Material::Bumpy { material, bump } => {
+                material.load(images)?;
+                bump.load(images)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Light given off by the material itself, added on top of whatever it scatters
+pub fn emitted(material: &Material, rec: &HitRecord) -> Color {
+    match material {
+        Material::DiffuseLight { emit, strength } => *strength * emit.value_at(rec),
+        Material::Principled {
+            emission,
+            emission_strength,
+            ..
+        } => *emission_strength * emission.value_at(rec),
+        Material::Bumpy { material, .. } => emitted(material, rec),
+        _ => Color::default(),
+    }
+}
+
+/// Tilts the shading normal of hits on `Bumpy` materials, and hands the hit on to the material underneath
+pub fn apply_bump(mut rec: HitRecord<'_>) -> HitRecord<'_> {
+    while let Material::Bumpy { material, bump } = rec.material {
+        rec.normal = bump.normal(&rec);
+        rec.material = material;
+    }
+
+    rec
+}
+
+pub fn scatter(
+    material: &Material,
+    ray_in: &Ray,
+    rec: &HitRecord,
+    rng: &mut impl Rng,
+) -> Option<(Color, Ray)> {
     match material {
         Material::Lambertian { albedo } => {
-            let target = rec.point + rec.normal + random_in_unit_sphere();
+            // Sampling proportionally to the cosine cancels it out of the rendering equation
+            let direction = cosine_weighted(&rec.facing_normal(ray_in), rng);
 
-            Some((*albedo, Ray::new(rec.point, target - rec.point)))
+            Some((albedo.value_at(rec), rec.spawn_ray(direction)))
         }
-        Material::Metal { albedo } => {
-            let reflected = reflect(&Vec3::unit_vector(&ray_in.direction), &rec.normal);
+        Material::Metal { albedo, fuzz } => {
+            let normal = rec.facing_normal(ray_in);
+            let reflected = reflect(&Vec3::unit_vector(&ray_in.direction), &normal);
+            let direction = reflected + fuzz.clamp(0.0, 1.0) * random_in_unit_sphere(rng);
 
-            if Vec3::dot(&Ray::default().direction, &rec.normal) > 0.0 {
-                Some((*albedo, Ray::new(rec.point, reflected)))
+            // Fuzzed reflections that end up below the surface are absorbed
+            if Vec3::dot(&direction, &normal) > 0.0 {
+                Some((albedo.value_at(rec), rec.spawn_ray(direction)))
             } else {
                 None
             }
@@ -59,20 +200,81 @@ pub fn scatter(material: &Material, ray_in: &Ray, rec: &HitRecord) -> Option<(Co
                     None => (Vec3::default(), 1.0),
                 };
 
-            let mut rng = rand::thread_rng();
-
             Some((
                 Color::new(1.0, 1.0, 1.0),
                 if rng.gen::<f64>() < refracted.1 {
-                    Ray::new(rec.point, reflected)
+                    rec.spawn_ray(reflected)
                 } else {
-                    Ray::new(rec.point, refracted.0)
+                    rec.spawn_ray(refracted.0)
                 },
             ))
         }
+        Material::Conductor { ior, roughness } => {
+            let bsdf = MicrofacetReflection::conductor(ior, *roughness);
+            sample_bsdf(&bsdf, &rec.facing_normal(ray_in), ray_in, rec, rng)
+        }
+        Material::RoughDielectric { ior, roughness } => {
+            // Transmission needs to know which side is the inside, so the frame uses the outward normal
+            let normal = Vec3::unit_vector(&rec.normal);
+            sample_bsdf(&RoughDielectric::new(*ior, *roughness), &normal, ray_in, rec, rng)
+        }
+        Material::Principled {
+            base_color,
+            metallic,
+            roughness,
+            metallic_roughness,
+            specular,
+            transmission,
+            ior,
+            clearcoat,
+            clearcoat_roughness,
+            sheen,
+            ..
+        } => {
+            let scale = metallic_roughness.as_ref().map_or(Color::new(1.0, 1.0, 1.0), |t| t.value_at(rec));
+            let bsdf = Principled {
+                base_color: base_color.value_at(rec),
+                metallic: (metallic * scale.b()).clamp(0.0, 1.0),
+                roughness: roughness * scale.g(),
+                specular: *specular,
+                transmission: transmission.clamp(0.0, 1.0),
+                ior: *ior,
+                clearcoat: clearcoat.clamp(0.0, 1.0),
+                clearcoat_roughness: *clearcoat_roughness,
+                sheen: *sheen,
+            };
+
+            // Opaque surfaces look the same from both sides, glass needs to know where the inside is
+            let normal = if bsdf.transmission > 0.0 {
+                Vec3::unit_vector(&rec.normal)
+            } else {
+                rec.facing_normal(ray_in)
+            };
+            sample_bsdf(&bsdf, &normal, ray_in, rec, rng)
+        }
+        Material::DiffuseLight { .. } => None,
+        Material::Bumpy { .. } => {
+            let rec = apply_bump(*rec);
+            scatter(rec.material, ray_in, &rec, rng)
+        }
     }
 }
 
+/// Scatters through a BSDF in the shading frame around `normal`
+fn sample_bsdf(
+    bsdf: &impl Bsdf,
+    normal: &Vec3,
+    ray_in: &Ray,
+    rec: &HitRecord,
+    rng: &mut impl Rng,
+) -> Option<(Color, Ray)> {
+    let frame = Onb::from_w(normal);
+    let wo = frame.to_local(&-Vec3::unit_vector(&ray_in.direction));
+    let sample = bsdf.sample(&wo, rng)?;
+
+    Some((sample.weight, rec.spawn_ray(frame.local(&sample.wi))))
+}
+
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     *v - 2.0 * Vec3::dot(v, n) * *n
 }
@@ -95,14 +297,86 @@ fn schlick(cosine: f64, ref_idx: f64) -> f64 {
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
-fn random_in_unit_sphere() -> Vec3 {
-    let mut rng = rand::thread_rng();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::test_record;
 
-    loop {
-        let p = 2.0 * Vec3::new(rng.gen::<f64>(), rng.gen::<f64>(), 0.0) - Vec3::new(1.0, 1.0, 0.0);
+    #[test]
+    fn test_principled_defaults() {
+        let material: Material = ron::from_str("Principled(metallic: 1.0, base_color: Vec3(1.0, 0.5, 0.0))").unwrap();
+        let Material::Principled {
+            base_color,
+            metallic,
+            roughness,
+            specular,
+            transmission,
+            ior,
+            emission,
+            emission_strength,
+            ..
+        } = material
+        else {
+            panic!("{material:?}");
+        };
 
-        if p.squared_length() < 1.0 {
-            return p;
-        }
+        assert_eq!(base_color.value(0.0, 0.0, &Vec3::default()), Color::new(1.0, 0.5, 0.0));
+        assert_eq!((metallic, roughness, specular, transmission, ior), (1.0, 0.5, 0.5, 0.0, 1.5));
+        assert_eq!((emission.value(0.0, 0.0, &Vec3::default()), emission_strength), (Color::default(), 1.0));
+        assert!(ron::from_str::<Material>("Principled()").is_ok());
+    }
+
+    /// Hit at the origin of a floor facing +y
+    fn record(material: &Material) -> HitRecord<'_> {
+        let tangents = (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        test_record(material, Vec3::default(), Vec3::new(0.0, 1.0, 0.0), tangents)
+    }
+
+    #[test]
+    fn test_principled_emission() {
+        let material: Material = ron::from_str("Principled(emission: Vec3(1.0, 0.5, 0.25), emission_strength: 4.0)").unwrap();
+
+        assert_eq!(emitted(&material, &record(&material)), Color::new(4.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn test_bumpy() {
+        let material: Material = ron::from_str(
+            "Bumpy(
+                material: Bumpy(
+                    material: DiffuseLight(emit: Vec3(1.0, 1.0, 1.0)),
+                    bump: Normal(texture: Vec3(1.0, 0.5, 1.0)),
+                ),
+                bump: Normal(texture: Vec3(0.5, 1.0, 1.0)),
+            )",
+        )
+        .unwrap();
+        let rec = apply_bump(record(&material));
+
+        // Both maps tilt the normal, and the hit ends up with the material underneath
+        assert!(matches!(rec.material, Material::DiffuseLight { .. }));
+        assert!(rec.normal.x() > 0.1 && rec.normal.z() < -0.1);
+        assert_eq!(rec.geometric_normal, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(emitted(&material, &record(&material)), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_bumpy_scatter() {
+        let material: Material = ron::from_str(
+            "Bumpy(
+                material: Metal(albedo: Vec3(1.0, 1.0, 1.0)),
+                bump: Normal(texture: Vec3(0.5, 0.75, 1.0)),
+            )",
+        )
+        .unwrap();
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+
+        // A mirror reflects straight down light about the tilted normal, and the ray starts above the surface
+        let (_, scattered) = scatter(&material, &ray, &record(&material), &mut rng).unwrap();
+        let normal = Vec3::unit_vector(&Vec3::new(0.0, 1.0, -0.5));
+        let expected = reflect(&ray.direction, &normal);
+        assert!((Vec3::unit_vector(&scattered.direction) - expected).length() < 1e-9);
+        assert!(scattered.origin.y() > 0.0);
     }
 }
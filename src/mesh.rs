@@ -0,0 +1,292 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::color::Color;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::transform::Mat4;
+use crate::triangle::{self, DEFAULT_UVS};
+use crate::vec3::Vec3;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A face refers to a vertex that doesn't exist
+    InvalidIndex { face: usize, index: usize },
+    /// Normals, UVs or colours are given, but not one for every vertex
+    MissingAttribute(&'static str),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidIndex { face, index } => write!(f, "face {face} refers to vertex {index}, which doesn't exist"),
+            Error::MissingAttribute(attribute) => write!(f, "mesh needs either no {attribute} or one for every vertex"),
+        }
+    }
+}
+
+/// Triangles sharing one list of vertices. Normals, UVs and colours are optional, when present there
+/// is one for every vertex
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshData {
+    pub vertices: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Color>,
+    /// Indices of the corners of every triangle, counterclockwise seen from the front
+    pub faces: Vec<[usize; 3]>,
+}
+
+impl MeshData {
+    pub fn new(
+        vertices: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f64, f64)>,
+        colors: Vec<Color>,
+        faces: Vec<[usize; 3]>,
+    ) -> Result<MeshData, Error> {
+        if !normals.is_empty() && normals.len() != vertices.len() {
+            return Err(Error::MissingAttribute("normals"));
+        }
+        if !uvs.is_empty() && uvs.len() != vertices.len() {
+            return Err(Error::MissingAttribute("uvs"));
+        }
+        if !colors.is_empty() && colors.len() != vertices.len() {
+            return Err(Error::MissingAttribute("colors"));
+        }
+        for (face, indices) in faces.iter().enumerate() {
+            if let Some(&index) = indices.iter().find(|&&i| i >= vertices.len()) {
+                return Err(Error::InvalidIndex { face, index });
+            }
+        }
+
+        Ok(MeshData {
+            vertices,
+            normals,
+            uvs,
+            colors,
+            faces,
+        })
+    }
+
+    /// Copy moved into place by `transform`, with the normals following along and the faces still
+    /// counterclockwise from the front when it mirrors
+    pub fn transformed(&self, transform: &Mat4) -> MeshData {
+        let normal_transform = transform.inverse().unwrap_or(Mat4::IDENTITY).transpose();
+        let mirrored = transform.determinant3() < 0.0;
+
+        MeshData {
+            vertices: self.vertices.iter().map(|v| transform.transform_point(v)).collect(),
+            normals: self
+                .normals
+                .iter()
+                .map(|n| Vec3::unit_vector(&normal_transform.transform_vector(n)))
+                .collect(),
+            uvs: self.uvs.clone(),
+            colors: self.colors.clone(),
+            faces: self
+                .faces
+                .iter()
+                .map(|&[a, b, c]| if mirrored { [a, c, b] } else { [a, b, c] })
+                .collect(),
+        }
+    }
+
+    fn corners(&self, face: usize) -> [Vec3; 3] {
+        self.faces[face].map(|i| self.vertices[i])
+    }
+}
+
+/// Vertex buffers together with the material they are rendered with
+#[derive(Debug)]
+struct Shared {
+    data: Arc<MeshData>,
+    material: Material,
+}
+
+/// One face of a mesh, looking up its corners in the shared buffers
+#[derive(Debug)]
+struct MeshTriangle {
+    mesh: Arc<Shared>,
+    face: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let data = &self.mesh.data;
+        let corners = data.corners(self.face);
+        let (t, barycentric) = triangle::intersect(r, &corners, t_min, t_max)?;
+
+        let indices = data.faces[self.face];
+        let normals = (!data.normals.is_empty()).then(|| indices.map(|i| data.normals[i]));
+        let colors = (!data.colors.is_empty()).then(|| indices.map(|i| data.colors[i]));
+        let uvs = if data.uvs.is_empty() {
+            DEFAULT_UVS
+        } else {
+            indices.map(|i| data.uvs[i])
+        };
+
+        Some(triangle::record(t, barycentric, &corners, normals.as_ref(), &uvs, colors.as_ref(), &self.mesh.material))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        triangle::bounds(&self.mesh.data.corners(self.face))
+    }
+}
+
+/// Triangle mesh, with a bounding volume hierarchy of its own so that large meshes are quick to hit
+#[derive(Debug)]
+pub struct Mesh {
+    triangles: Bvh,
+}
+
+impl Mesh {
+    pub fn new(data: Arc<MeshData>, material: Material) -> Mesh {
+        let faces = data.faces.len();
+        let mesh = Arc::new(Shared { data, material });
+        let triangles = (0..faces)
+            .map(|face| {
+                Box::new(MeshTriangle {
+                    mesh: mesh.clone(),
+                    face,
+                }) as Box<dyn Hittable>
+            })
+            .collect();
+
+        Mesh {
+            triangles: Bvh::new(triangles),
+        }
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.triangles.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.triangles.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::{Rotation, Transform};
+
+    /// Unit cube around the origin, with its faces pointing outwards
+    fn cube() -> MeshData {
+        let vertices = (0..8)
+            .map(|i| Vec3::new((i & 1) as f64 - 0.5, ((i >> 1) & 1) as f64 - 0.5, ((i >> 2) & 1) as f64 - 0.5))
+            .collect();
+        let faces = vec![
+            [0, 2, 1],
+            [1, 2, 3],
+            [4, 5, 6],
+            [5, 7, 6],
+            [0, 1, 4],
+            [1, 5, 4],
+            [2, 6, 3],
+            [3, 6, 7],
+            [0, 4, 2],
+            [2, 4, 6],
+            [1, 3, 5],
+            [3, 7, 5],
+        ];
+
+        MeshData::new(vertices, Vec::new(), Vec::new(), Vec::new(), faces).unwrap()
+    }
+
+    #[test]
+    fn test_validation() {
+        let vertices = vec![Vec3::default(); 3];
+
+        assert!(MeshData::new(vertices.clone(), Vec::new(), Vec::new(), Vec::new(), vec![[0, 1, 2]]).is_ok());
+        assert!(matches!(
+            MeshData::new(vertices.clone(), Vec::new(), Vec::new(), Vec::new(), vec![[0, 1, 2], [2, 3, 0]]),
+            Err(Error::InvalidIndex { face: 1, index: 3 })
+        ));
+        assert!(matches!(
+            MeshData::new(vertices.clone(), vec![Vec3::default()], Vec::new(), Vec::new(), Vec::new()),
+            Err(Error::MissingAttribute("normals"))
+        ));
+        assert!(matches!(
+            MeshData::new(vertices.clone(), Vec::new(), vec![(0.0, 0.0); 4], Vec::new(), Vec::new()),
+            Err(Error::MissingAttribute("uvs"))
+        ));
+        assert!(matches!(
+            MeshData::new(vertices, Vec::new(), Vec::new(), vec![Color::default(); 2], Vec::new()),
+            Err(Error::MissingAttribute("colors"))
+        ));
+    }
+
+    #[test]
+    fn test_closed_mesh() {
+        let mesh = Mesh::new(Arc::new(cube()), Material::default());
+        let directions = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.3, 0.2, 1.0),
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 0.0),
+        ];
+
+        assert_eq!(mesh.bounding_box().centroid(), Vec3::default());
+        for direction in directions {
+            // From outside the first hit faces the ray, from inside it faces away, along every edge and corner too
+            let outside = mesh.hit(&Ray::new(-5.0 * direction, direction), 0.0, f64::MAX).unwrap();
+            let inside = mesh.hit(&Ray::new(Vec3::default(), direction), 0.0, f64::MAX).unwrap();
+
+            assert!(Vec3::dot(&outside.geometric_normal, &direction) < 0.0);
+            assert!(Vec3::dot(&inside.geometric_normal, &direction) > 0.0);
+            assert!(outside.point.x().abs().max(outside.point.y().abs()).max(outside.point.z().abs()) - 0.5 < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_transformed() {
+        let transform = Transform {
+            translate: Vec3::new(0.0, 2.0, 0.0),
+            rotate: Rotation::default(),
+            scale: Vec3::new(-2.0, 1.0, 1.0),
+        };
+        let mut data = cube();
+        data.normals = data.vertices.clone();
+        let moved = data.transformed(&transform.matrix());
+
+        assert_eq!(moved.vertices[1], Vec3::new(-1.0, 1.5, -0.5));
+        assert!((moved.normals[1] - Vec3::unit_vector(&Vec3::new(-0.25, -0.5, -0.5))).length() < 1e-9);
+
+        // Mirrored faces are turned around, so that the mesh is still closed with its faces outwards
+        let mesh = Mesh::new(Arc::new(moved), Material::default());
+        let direction = Vec3::new(1.0, 0.2, 0.1);
+        let rec = mesh.hit(&Ray::new(Vec3::new(0.0, 2.0, 0.0) - 5.0 * direction, direction), 0.0, f64::MAX).unwrap();
+        assert!(Vec3::dot(&rec.geometric_normal, &direction) < 0.0);
+    }
+
+    #[test]
+    fn test_shared_attributes() {
+        let data = MeshData::new(
+            vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)],
+            vec![Vec3::new(0.0, 0.0, 1.0); 4],
+            vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            Vec::new(),
+            vec![[0, 1, 2], [0, 2, 3]],
+        )
+        .unwrap();
+        let mesh = Mesh::new(Arc::new(data), Material::default());
+
+        // UVs run smoothly across both triangles of the quad
+        for (x, y) in [(0.75, 0.25), (0.25, 0.75)] {
+            let rec = mesh
+                .hit(&Ray::new(Vec3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::MAX)
+                .unwrap();
+            assert!((rec.u - x).abs() < 1e-9 && (rec.v - y).abs() < 1e-9);
+            assert!((rec.dpdu - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+            assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        }
+    }
+}
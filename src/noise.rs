@@ -0,0 +1,205 @@
+use crate::sampling::mix;
+use crate::vec3::Vec3;
+
+/// Directions to the edges of a cube, the gradients of improved Perlin noise
+const GRADIENTS: [(f64, f64, f64); 12] = [
+    (1.0, 1.0, 0.0),
+    (-1.0, 1.0, 0.0),
+    (1.0, -1.0, 0.0),
+    (-1.0, -1.0, 0.0),
+    (1.0, 0.0, 1.0),
+    (-1.0, 0.0, 1.0),
+    (1.0, 0.0, -1.0),
+    (-1.0, 0.0, -1.0),
+    (0.0, 1.0, 1.0),
+    (0.0, -1.0, 1.0),
+    (0.0, 1.0, -1.0),
+    (0.0, -1.0, -1.0),
+];
+
+/// Random bits for a lattice cell, only depending on the cell and the seed
+fn hash(seed: u64, x: i64, y: i64, z: i64) -> u64 {
+    mix(seed ^ mix(x as u64 ^ mix(y as u64 ^ mix(z as u64))))
+}
+
+/// Number in [0, 1) taken from the top bits of a hash
+fn unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn lattice(p: &Vec3) -> ([i64; 3], [f64; 3]) {
+    let cell = [p.x().floor(), p.y().floor(), p.z().floor()];
+    (
+        cell.map(|c| c as i64),
+        [p.x() - cell[0], p.y() - cell[1], p.z() - cell[2]],
+    )
+}
+
+/// Smooth step with zero first and second derivatives at 0 and 1
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// Gradient noise, zero on the integer lattice and roughly within -1.0..1.0 in between
+pub fn perlin(p: &Vec3, seed: u64) -> f64 {
+    let ([x, y, z], [fx, fy, fz]) = lattice(p);
+
+    let corner = |i: i64, j: i64, k: i64| {
+        let (gx, gy, gz) = GRADIENTS[(hash(seed, x + i, y + j, z + k) % 12) as usize];
+        gx * (fx - i as f64) + gy * (fy - j as f64) + gz * (fz - k as f64)
+    };
+
+    let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+    lerp(
+        w,
+        lerp(v, lerp(u, corner(0, 0, 0), corner(1, 0, 0)), lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+        lerp(v, lerp(u, corner(0, 0, 1), corner(1, 0, 1)), lerp(u, corner(0, 1, 1), corner(1, 1, 1))),
+    )
+}
+
+/// Sum of `octaves` layers of noise, each twice as detailed and half as strong as the last,
+/// divided by the total strength
+fn octaves(p: &Vec3, octaves: u32, seed: u64, layer: impl Fn(&Vec3, u64) -> f64) -> f64 {
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut p = *p;
+
+    for octave in 0..octaves.max(1) {
+        sum += amplitude * layer(&p, seed.wrapping_add(octave as u64));
+        total += amplitude;
+        amplitude *= 0.5;
+        p = p * 2.0;
+    }
+
+    sum / total
+}
+
+/// Fractal Brownian motion, Perlin noise with finer details, within -1.0..1.0
+pub fn fbm(p: &Vec3, octaves: u32, seed: u64) -> f64 {
+    self::octaves(p, octaves, seed, perlin).clamp(-1.0, 1.0)
+}
+
+/// Like `fbm`, but with the absolute value of every octave, giving billowy shapes within 0.0..1.0
+pub fn turbulence(p: &Vec3, octaves: u32, seed: u64) -> f64 {
+    self::octaves(p, octaves, seed, |p, seed| perlin(p, seed).abs()).min(1.0)
+}
+
+/// The closest feature points to a point in Worley (Voronoi) noise
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cells {
+    /// Distance to the closest feature point
+    pub f1: f64,
+    /// Distance to the second closest feature point
+    pub f2: f64,
+    /// Random number in [0, 1) for the cell of the closest feature point
+    pub id: f64,
+}
+
+/// Worley noise with one feature point per unit cell, moved up to `jitter` (0.0-1.0) away from
+/// the cell centre
+pub fn voronoi(p: &Vec3, jitter: f64, seed: u64) -> Cells {
+    let ([x, y, z], _) = lattice(p);
+    let jitter = jitter.clamp(0.0, 1.0);
+    let mut cells = Cells {
+        f1: f64::INFINITY,
+        f2: f64::INFINITY,
+        id: 0.0,
+    };
+
+    for i in x - 1..=x + 1 {
+        for j in y - 1..=y + 1 {
+            for k in z - 1..=z + 1 {
+                let h = hash(seed, i, j, k);
+                let offset = |n: u64| 0.5 + jitter * (unit(mix(h ^ n)) - 0.5);
+                let feature = Vec3::new(i as f64 + offset(1), j as f64 + offset(2), k as f64 + offset(3));
+                let distance = (feature - *p).length();
+
+                if distance < cells.f1 {
+                    cells = Cells {
+                        f1: distance,
+                        f2: cells.f1,
+                        id: unit(h),
+                    };
+                } else if distance < cells.f2 {
+                    cells.f2 = distance;
+                }
+            }
+        }
+    }
+
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> impl Iterator<Item = Vec3> {
+        (0..2000).map(|i| {
+            let t = i as f64;
+            Vec3::new((t * 0.37).sin() * 9.0, t * 0.013 - 7.0, (t * 0.11).cos() * 5.0)
+        })
+    }
+
+    #[test]
+    fn test_perlin() {
+        assert_eq!(perlin(&Vec3::new(3.0, -2.0, 5.0), 1), 0.0);
+
+        let values: Vec<f64> = points().map(|p| perlin(&p, 1)).collect();
+        assert!(values.iter().all(|v| v.abs() <= 1.1));
+        assert!(values.iter().any(|&v| v > 0.3) && values.iter().any(|&v| v < -0.3));
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        assert!(mean.abs() < 0.05);
+
+        // Continuous across cell borders
+        let p = Vec3::new(1.0, 0.3, 0.6);
+        let e = Vec3::new(1e-7, 0.0, 0.0);
+        assert!((perlin(&(p - e), 1) - perlin(&(p + e), 1)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_noise_seed() {
+        let p = Vec3::new(0.4, 1.7, -2.2);
+
+        assert_eq!(perlin(&p, 5), perlin(&p, 5));
+        assert_ne!(perlin(&p, 5), perlin(&p, 6));
+        assert_eq!(voronoi(&p, 1.0, 5), voronoi(&p, 1.0, 5));
+        assert_ne!(voronoi(&p, 1.0, 5), voronoi(&p, 1.0, 6));
+    }
+
+    #[test]
+    fn test_fbm_and_turbulence() {
+        for p in points() {
+            let f = fbm(&p, 6, 2);
+            let t = turbulence(&p, 6, 2);
+            assert!((-1.0..=1.0).contains(&f));
+            assert!((0.0..=1.0).contains(&t));
+        }
+
+        // A single octave is plain Perlin noise
+        let p = Vec3::new(0.4, 1.7, -2.2);
+        assert_eq!(fbm(&p, 1, 3), perlin(&p, 3));
+        assert_eq!(turbulence(&p, 1, 3), perlin(&p, 3).abs());
+    }
+
+    #[test]
+    fn test_voronoi() {
+        for p in points() {
+            let cells = voronoi(&p, 1.0, 4);
+            assert!(cells.f1 <= cells.f2);
+            assert!((0.0..1.0).contains(&cells.id));
+            // The closest point is never further away than the far corner of a neighbouring cell
+            assert!(cells.f1 < 3f64.sqrt());
+        }
+
+        // Without jitter the feature points sit in the cell centres
+        let cells = voronoi(&Vec3::new(2.5, -0.5, 7.5), 0.0, 4);
+        assert_eq!(cells.f1, 0.0);
+        assert_eq!(cells.f2, 1.0);
+    }
+}
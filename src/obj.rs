@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::color::Color;
+use crate::material::Material;
+use crate::mesh::{self, MeshData};
+use crate::texture::{ImageFile, Texture};
+use crate::vec3::Vec3;
+
+#[derive(Debug)]
+pub enum Error {
+    FailedToRead(PathBuf, std::io::Error),
+    InvalidData { line: usize, reason: &'static str },
+    InvalidMesh(mesh::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::FailedToRead(path, e) => write!(f, "failed to read {}: {e}", path.display()),
+            Error::InvalidData { line, reason } => write!(f, "invalid model data on line {line}: {reason}"),
+            Error::InvalidMesh(e) => write!(f, "invalid model: {e}"),
+        }
+    }
+}
+
+/// Faces of one group that share a material
+#[derive(Debug)]
+pub struct ObjMesh {
+    /// Name of the `o` or `g` statement the faces follow, empty before the first one
+    pub group: String,
+    pub material: Material,
+    pub data: Arc<MeshData>,
+}
+
+/// Wavefront OBJ model, loaded when the config is read
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "PathBuf", into = "PathBuf")]
+pub struct ObjFile {
+    path: PathBuf,
+    pub meshes: Arc<Vec<ObjMesh>>,
+}
+
+impl TryFrom<PathBuf> for ObjFile {
+    type Error = Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        let source = std::fs::read_to_string(&path).map_err(|e| Error::FailedToRead(path.clone(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let meshes = parse_obj(&source, dir)?;
+
+        Ok(ObjFile {
+            path,
+            meshes: Arc::new(meshes),
+        })
+    }
+}
+
+impl From<ObjFile> for PathBuf {
+    fn from(file: ObjFile) -> Self {
+        file.path
+    }
+}
+
+/// Index into one of the vertex lists, 1-based from the start or negative from the end
+fn index(token: &str, count: usize, line: usize) -> Result<usize, Error> {
+    let invalid = Error::InvalidData {
+        line,
+        reason: "face refers to a missing vertex",
+    };
+    let i: i64 = token.parse().map_err(|_| Error::InvalidData {
+        line,
+        reason: "invalid face index",
+    })?;
+
+    let resolved = if i < 0 { count as i64 + i } else { i - 1 };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(invalid);
+    }
+
+    Ok(resolved as usize)
+}
+
+fn numbers<const N: usize>(tokens: &[&str], line: usize) -> Result<[f64; N], Error> {
+    let mut values = [0.0; N];
+    for (value, token) in values.iter_mut().zip(tokens) {
+        *value = token.parse().map_err(|_| Error::InvalidData {
+            line,
+            reason: "invalid number",
+        })?;
+    }
+
+    if tokens.len() < N {
+        return Err(Error::InvalidData {
+            line,
+            reason: "missing numbers",
+        });
+    }
+
+    Ok(values)
+}
+
+/// Corner of a face, as indices into the positions, UVs and normals
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// Faces gathered for one group and material, before they are turned into a mesh
+#[derive(Default)]
+struct Builder {
+    corners: HashMap<Corner, usize>,
+    unique: Vec<Corner>,
+    faces: Vec<[usize; 3]>,
+}
+
+impl Builder {
+    fn add(&mut self, face: &[Corner]) {
+        let mut vertex = |corner: Corner| {
+            *self.corners.entry(corner).or_insert_with(|| {
+                self.unique.push(corner);
+                self.unique.len() - 1
+            })
+        };
+        let indices: Vec<usize> = face.iter().map(|&c| vertex(c)).collect();
+
+        // Fan around the first corner, which is right for the convex polygons modelling programs write
+        for i in 1..indices.len() - 1 {
+            self.faces.push([indices[0], indices[i], indices[i + 1]]);
+        }
+    }
+
+    fn build(self, positions: &[Vec3], uvs: &[(f64, f64)], normals: &[Vec3]) -> Result<MeshData, Error> {
+        let vertices = self.unique.iter().map(|c| positions[c.0]).collect();
+
+        // Smooth shading needs a normal at every corner, otherwise the whole mesh is shaded flat
+        let normals = if self.unique.iter().all(|c| c.2.is_some()) {
+            self.unique.iter().filter_map(|c| c.2).map(|i| normals[i]).collect()
+        } else {
+            Vec::new()
+        };
+        let uvs = if self.unique.iter().any(|c| c.1.is_some()) {
+            self.unique.iter().map(|c| c.1.map_or((0.0, 0.0), |i| uvs[i])).collect()
+        } else {
+            Vec::new()
+        };
+
+        MeshData::new(vertices, normals, uvs, Vec::new(), self.faces).map_err(Error::InvalidMesh)
+    }
+}
+
+/// Parses an OBJ file, loading material libraries and textures relative to `dir`
+pub fn parse_obj(source: &str, dir: &Path) -> Result<Vec<ObjMesh>, Error> {
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+    let mut materials = HashMap::new();
+
+    let mut group = String::new();
+    let mut material = String::new();
+    // Meshes in the order they first appear in the file
+    let mut builders: Vec<((String, String), Builder)> = Vec::new();
+    let mut indices: HashMap<(String, String), usize> = HashMap::new();
+    // Builder for the current group and material, looked up again when either changes
+    let mut current = None;
+
+    for (number, line) in source.lines().enumerate() {
+        let line_number = number + 1;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let rest = line[keyword.len()..].trim();
+        let tokens: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let [x, y, z] = numbers(&tokens, line_number)?;
+                positions.push(Vec3::new(x, y, z));
+            }
+            "vt" => {
+                // The third coordinate of 3D texture coordinates isn't used
+                let [u, v] = numbers(&tokens, line_number)?;
+                uvs.push((u, v));
+            }
+            "vn" => {
+                let [x, y, z] = numbers(&tokens, line_number)?;
+                normals.push(Vec3::new(x, y, z));
+            }
+            "f" => {
+                if tokens.len() < 3 {
+                    return Err(Error::InvalidData {
+                        line: line_number,
+                        reason: "face with less than three corners",
+                    });
+                }
+
+                let face = tokens
+                    .iter()
+                    .map(|corner| {
+                        let mut parts = corner.split('/');
+                        let position = index(parts.next().unwrap_or_default(), positions.len(), line_number)?;
+                        let optional = |part: Option<&str>, count| match part {
+                            None | Some("") => Ok(None),
+                            Some(token) => index(token, count, line_number).map(Some),
+                        };
+                        let uv = optional(parts.next(), uvs.len())?;
+                        let normal = optional(parts.next(), normals.len())?;
+
+                        Ok((position, uv, normal))
+                    })
+                    .collect::<Result<Vec<Corner>, Error>>()?;
+
+                let i = *current.get_or_insert_with(|| {
+                    let key = (group.clone(), material.clone());
+                    *indices.entry(key.clone()).or_insert_with(|| {
+                        builders.push((key, Builder::default()));
+                        builders.len() - 1
+                    })
+                });
+                builders[i].1.add(&face);
+            }
+            "o" | "g" => {
+                group = rest.to_string();
+                current = None;
+            }
+            "usemtl" => {
+                material = rest.to_string();
+                current = None;
+            }
+            "mtllib" => {
+                for name in &tokens {
+                    let path = dir.join(name);
+                    // Models can still be rendered with the material from the config without their library
+                    match std::fs::read_to_string(&path) {
+                        Ok(source) => materials.extend(parse_mtl(&source, path.parent().unwrap_or(dir))?),
+                        Err(e) => eprintln!("Skipping material library {}: {e}", path.display()),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    builders
+        .into_iter()
+        .map(|((group, material), builder)| {
+            Ok(ObjMesh {
+                group,
+                material: materials
+                    .get(&material)
+                    .cloned()
+                    .unwrap_or_else(|| principled(&MtlMaterial::default())),
+                data: Arc::new(builder.build(&positions, &uvs, &normals)?),
+            })
+        })
+        .collect()
+}
+
+/// Material as described by an MTL file
+#[derive(Debug, Clone)]
+struct MtlMaterial {
+    diffuse: Color,
+    diffuse_map: Option<ImageFile>,
+    specular: Option<Color>,
+    shininess: Option<f64>,
+    ior: f64,
+    dissolve: f64,
+    emission: Color,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            diffuse_map: None,
+            specular: None,
+            shininess: None,
+            ior: 1.5,
+            dissolve: 1.0,
+            emission: Color::default(),
+        }
+    }
+}
+
+/// Principled material closest to an MTL material, converting the way Blender does
+fn principled(mtl: &MtlMaterial) -> Material {
+    let base_color = match &mtl.diffuse_map {
+        Some(image) => Texture::Image {
+            path: image.clone(),
+            srgb: None,
+            wrap: Default::default(),
+            wrap_v: None,
+            filter: Default::default(),
+            scale: (1.0, 1.0),
+            offset: (0.0, 0.0),
+        },
+        None => mtl.diffuse.into(),
+    };
+
+    Material::Principled {
+        base_color,
+        metallic: 0.0,
+        // Blender writes the roughness r as the exponent Ns = 1000 * (1 - r)^2
+        roughness: mtl.shininess.map_or(0.5, |ns| 1.0 - (ns.clamp(0.0, 1000.0) / 1000.0).sqrt()),
+        metallic_roughness: None,
+        specular: mtl.specular.map_or(0.5, |ks| ((ks.r() + ks.g() + ks.b()) / 3.0).clamp(0.0, 1.0)),
+        transmission: (1.0 - mtl.dissolve).clamp(0.0, 1.0),
+        ior: mtl.ior,
+        clearcoat: 0.0,
+        clearcoat_roughness: 0.03,
+        sheen: 0.0,
+        emission: mtl.emission.into(),
+        emission_strength: 1.0,
+    }
+}
+
+/// Parses an MTL material library, loading textures relative to `dir`
+pub fn parse_mtl(source: &str, dir: &Path) -> Result<HashMap<String, Material>, Error> {
+    let mut materials: Vec<(String, MtlMaterial)> = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let line_number = number + 1;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let rest = line[keyword.len()..].trim();
+        let tokens: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            materials.push((rest.to_string(), MtlMaterial::default()));
+            continue;
+        }
+        let Some((_, material)) = materials.last_mut() else {
+            continue;
+        };
+        let color = |tokens: &[&str]| numbers::<3>(tokens, line_number).map(|[r, g, b]| Color::new(r, g, b));
+
+        match keyword {
+            "Kd" => material.diffuse = color(&tokens)?,
+            "Ks" => material.specular = Some(color(&tokens)?),
+            "Ke" => material.emission = color(&tokens)?,
+            "Ns" => material.shininess = Some(numbers::<1>(&tokens, line_number)?[0]),
+            "Ni" => material.ior = numbers::<1>(&tokens, line_number)?[0],
+            "d" => material.dissolve = numbers::<1>(&tokens, line_number)?[0],
+            "Tr" => material.dissolve = 1.0 - numbers::<1>(&tokens, line_number)?[0],
+            "map_Kd" => {
+                // Options like `-s 2 2 2` come before the file name
+                let file = if rest.starts_with('-') {
+                    tokens.last().copied().unwrap_or_default()
+                } else {
+                    rest
+                };
+                // Loaded with the other textures when the config is processed
+                material.diffuse_map = Some(ImageFile::from(dir.join(file)));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(materials
+        .into_iter()
+        .map(|(name, material)| (name, principled(&material)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUADS: &str = "
+        # Two quads sharing an edge, and a triangle with negative indices
+        mtllib missing.mtl
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        v 2 0 0
+        v 2 1 0
+        vt 0 0
+        vt 1 0
+        vt 1 1
+        vt 0 1
+        vn 0 0 1
+        o left
+        f 1/1/1 2/2/1 3/3/1 4/4/1
+        g right
+        usemtl shiny
+        f 2//1 5//1 6//1 3//1
+        v 0 0 1
+        f -1 -7 -6
+    ";
+
+    fn parse(source: &str) -> Vec<ObjMesh> {
+        parse_obj(source, Path::new("")).unwrap()
+    }
+
+    #[test]
+    fn test_parse_obj() {
+        let meshes = parse(QUADS);
+        let groups: Vec<&str> = meshes.iter().map(|m| m.group.as_str()).collect();
+        assert_eq!(groups, ["left", "right"]);
+
+        // Quads are split into two triangles, sharing the corners that are alike
+        let left = &meshes[0].data;
+        assert_eq!(left.vertices.len(), 4);
+        assert_eq!(left.faces, [[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(left.uvs[2], (1.0, 1.0));
+        assert_eq!(left.normals, [Vec3::new(0.0, 0.0, 1.0); 4]);
+
+        // The last triangle has no normals, so the mesh it joins is flat shaded and has no UVs
+        let right = &meshes[1].data;
+        assert_eq!(right.faces.len(), 3);
+        assert_eq!(right.vertices[right.faces[2][0]], Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(right.vertices[right.faces[2][1]], Vec3::new(0.0, 0.0, 0.0));
+        assert!(right.normals.is_empty() && right.uvs.is_empty());
+    }
+
+    #[test]
+    fn test_mtllib_and_groups() {
+        // Each library on the line is loaded, and faces going back to a group join its mesh
+        let source = "
+            mtllib missing.mtl models.mtl
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            g a
+            usemtl paint
+            f 1 2 3
+            g b
+            f 1 2 3
+            g a
+            f 3 2 1
+        ";
+        let meshes = parse_obj(source, Path::new("tests/scenes")).unwrap();
+        let groups: Vec<(&str, usize)> = meshes.iter().map(|m| (m.group.as_str(), m.data.faces.len())).collect();
+        assert_eq!(groups, [("a", 2), ("b", 1)]);
+
+        let Material::Principled { base_color, .. } = &meshes[0].material else {
+            panic!("{:?}", meshes[0].material);
+        };
+        assert_eq!(base_color.value(0.0, 0.0, &Vec3::default()), Color::new(0.7, 0.15, 0.1));
+    }
+
+    #[test]
+    fn test_invalid_obj() {
+        let error = |source| parse_obj(source, Path::new("")).unwrap_err().to_string();
+
+        assert_eq!(error("v 0 0 0\nv 1 0 0\nf 1 2 3"), "invalid model data on line 3: face refers to a missing vertex");
+        assert_eq!(error("v 0 0\n"), "invalid model data on line 1: missing numbers");
+        assert_eq!(error("v 0 0 0\nf 1 1"), "invalid model data on line 2: face with less than three corners");
+    }
+
+    #[test]
+    fn test_parse_mtl() {
+        let materials = parse_mtl(
+            "
+            newmtl glass
+            Kd 0.1 0.2 0.3
+            Ks 0.25 0.25 0.25
+            Ns 250
+            Ni 1.33
+            d 0.25
+
+            newmtl lamp
+            Ke 4 3 2
+
+            newmtl label
+            map_Kd -s 2 2 1 label.png
+            ",
+            Path::new("tests/scenes"),
+        )
+        .unwrap();
+
+        let Material::Principled {
+            base_color,
+            roughness,
+            specular,
+            transmission,
+            ior,
+            ..
+        } = &materials["glass"]
+        else {
+            panic!("{:?}", materials["glass"]);
+        };
+        assert_eq!(base_color.value(0.0, 0.0, &Vec3::default()), Color::new(0.1, 0.2, 0.3));
+        assert_eq!((*roughness, *specular, *transmission, *ior), (0.5, 0.25, 0.75, 1.33));
+
+        let Material::Principled { emission, .. } = &materials["lamp"] else {
+            panic!();
+        };
+        assert_eq!(emission.value(0.0, 0.0, &Vec3::default()), Color::new(4.0, 3.0, 2.0));
+
+        assert!(matches!(
+            &materials["label"],
+            Material::Principled {
+                base_color: Texture::Image { .. },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_missing_material() {
+        // Materials that the library doesn't define are light grey
+        let meshes = parse(QUADS);
+        let Material::Principled { base_color, .. } = &meshes[1].material else {
+            panic!("{:?}", meshes[1].material);
+        };
+        assert_eq!(base_color.value(0.0, 0.0, &Vec3::default()), Color::new(0.8, 0.8, 0.8));
+    }
+}
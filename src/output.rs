@@ -0,0 +1,257 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::color::Color;
+use crate::exr::{self, write_exr};
+use crate::render::Framebuffer;
+use crate::tone_map::{srgb_oetf, ToneMap};
+
+const MAX_RGB_VALUE: u8 = 255; // Max value in RGB colours (0...255)
+
+#[derive(Debug)]
+pub enum Error {
+    UnsupportedFormat(String),
+    FailedToWrite(std::io::Error),
+    FailedToEncode(png::EncodingError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnsupportedFormat(extension) => {
+                write!(f, "unsupported output format: {extension:?}")
+            }
+            Error::FailedToWrite(e) => write!(f, "failed to write image: {e}"),
+            Error::FailedToEncode(e) => write!(f, "failed to encode image: {e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::FailedToWrite(e)
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(e: png::EncodingError) -> Self {
+        Error::FailedToEncode(e)
+    }
+}
+
+/// Image file formats the renderer can write, picked from the output file extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Ppm,
+    Png,
+    Pfm,
+    Exr,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Result<Format, Error> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        match extension.as_str() {
+            "ppm" => Ok(Format::Ppm),
+            "png" => Ok(Format::Png),
+            "pfm" => Ok(Format::Pfm),
+            "exr" => Ok(Format::Exr),
+            _ => Err(Error::UnsupportedFormat(extension)),
+        }
+    }
+}
+
+/// Display transform of the 8-bit formats: tone mapping followed by sRGB encoding
+fn to_rgb8(col: Color, tone_map: ToneMap) -> [u8; 3] {
+    let mapped = tone_map.apply(col);
+    let adjust = |f: f64| (255.99 * srgb_oetf(f)) as u8;
+
+    [adjust(mapped.r()), adjust(mapped.g()), adjust(mapped.b())]
+}
+
+/// ASCII PPM, as printed to stdout when no output file is given
+pub fn write_ppm_ascii(out: &mut impl Write, framebuffer: &Framebuffer, tone_map: ToneMap) -> Result<(), Error> {
+    writeln!(out, "P3\n{} {}\n{MAX_RGB_VALUE}", framebuffer.width, framebuffer.height)?;
+
+    for row in framebuffer.rows() {
+        for &pixel in row {
+            let [ir, ig, ib] = to_rgb8(pixel, tone_map);
+            writeln!(out, "{ir} {ig} {ib}")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_ppm_binary(out: &mut impl Write, framebuffer: &Framebuffer, tone_map: ToneMap) -> Result<(), Error> {
+    write!(out, "P6\n{} {}\n{MAX_RGB_VALUE}\n", framebuffer.width, framebuffer.height)?;
+
+    let bytes: Vec<u8> = framebuffer
+        .pixels
+        .iter()
+        .flat_map(|&p| to_rgb8(p, tone_map))
+        .collect();
+    out.write_all(&bytes)?;
+
+    Ok(())
+}
+
+fn write_png(out: impl Write, framebuffer: &Framebuffer, alpha: bool, tone_map: ToneMap) -> Result<(), Error> {
+    let mut encoder = png::Encoder::new(out, framebuffer.width, framebuffer.height);
+    encoder.set_color(if alpha {
+        png::ColorType::Rgba
+    } else {
+        png::ColorType::Rgb
+    });
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    let bytes: Vec<u8> = (0..framebuffer.pixels.len())
+        .flat_map(|i| {
+            if alpha {
+                // PNG colours aren't premultiplied, and the background mustn't show through twice
+                let coverage = framebuffer.alpha[i];
+                let color = if coverage > 0.0 {
+                    framebuffer.foreground(i) / coverage
+                } else {
+                    Color::default()
+                };
+                let [r, g, b] = to_rgb8(color, tone_map);
+
+                vec![r, g, b, (255.99 * coverage) as u8]
+            } else {
+                to_rgb8(framebuffer.pixels[i], tone_map).to_vec()
+            }
+        })
+        .collect();
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&bytes)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Portable Float Map with the unclamped linear radiance
+fn write_pfm(out: &mut impl Write, framebuffer: &Framebuffer) -> Result<(), Error> {
+    // A negative scale marks little endian data
+    write!(out, "PF\n{} {}\n-1.0\n", framebuffer.width, framebuffer.height)?;
+
+    // Rows go from the bottom of the image to the top
+    let bytes: Vec<u8> = framebuffer
+        .rows()
+        .rev()
+        .flatten()
+        .flat_map(|p| [p.r() as f32, p.g() as f32, p.b() as f32])
+        .flat_map(f32::to_le_bytes)
+        .collect();
+    out.write_all(&bytes)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Add the share of samples that hit an object as transparency, for the formats that support it
+    pub alpha: bool,
+    pub exr_type: exr::PixelType,
+    pub exr_compression: exr::Compression,
+    /// Display transform of the 8-bit formats, float formats are written linear
+    pub tone_map: ToneMap,
+}
+
+/// Writes the framebuffer to `path`, in the format matching its extension
+pub fn write(path: &Path, framebuffer: &Framebuffer, options: &Options) -> Result<(), Error> {
+    let format = Format::from_path(path)?;
+    let mut out = BufWriter::new(File::create(path)?);
+
+    if options.alpha && (format == Format::Ppm || format == Format::Pfm) {
+        eprintln!("{format:?} has no alpha channel, writing an opaque image");
+    }
+
+    match format {
+        Format::Ppm => write_ppm_binary(&mut out, framebuffer, options.tone_map)?,
+        Format::Png => write_png(&mut out, framebuffer, options.alpha, options.tone_map)?,
+        Format::Pfm => write_pfm(&mut out, framebuffer)?,
+        Format::Exr => write_exr(
+            &mut out,
+            framebuffer,
+            options.alpha,
+            options.exr_type,
+            options.exr_compression,
+        )?,
+    }
+
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path(Path::new("out/a.PNG")).unwrap(), Format::Png);
+        assert_eq!(Format::from_path(Path::new("a.ppm")).unwrap(), Format::Ppm);
+        assert_eq!(Format::from_path(Path::new("a.exr")).unwrap(), Format::Exr);
+        assert!(Format::from_path(Path::new("a.jpg")).is_err());
+        assert!(Format::from_path(Path::new("a")).is_err());
+    }
+
+    #[test]
+    fn test_write_ppm_binary() {
+        let mut framebuffer = Framebuffer::new(2, 1);
+        framebuffer.pixels = vec![Color::new(1.0, 0.5, 0.0), Color::new(2.0, -1.0, 0.25)];
+
+        let mut out = Vec::new();
+        write_ppm_binary(&mut out, &framebuffer, ToneMap::Clamp).unwrap();
+
+        assert_eq!(out, b"P6\n2 1\n255\n\xff\xbc\x00\xff\x00\x89");
+    }
+
+    #[test]
+    fn test_write_png_alpha() {
+        // Half of the samples hit a red object, the other half saw a blue background
+        let mut framebuffer = Framebuffer::new(2, 1);
+        framebuffer.pixels = vec![Color::new(0.5, 0.0, 0.5), Color::new(0.0, 0.0, 1.0)];
+        framebuffer.alpha = vec![0.5, 0.0];
+        framebuffer.background = vec![Color::new(0.0, 0.0, 0.5), Color::new(0.0, 0.0, 1.0)];
+
+        let mut out = Vec::new();
+        write_png(&mut out, &framebuffer, true, ToneMap::Clamp).unwrap();
+        let mut reader = png::Decoder::new(out.as_slice()).read_info().unwrap();
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut rgba).unwrap();
+
+        // The covered part keeps the colour of the object, without any blue fringe
+        assert_eq!(rgba, [255, 0, 0, 127, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_write_pfm_roundtrip() {
+        let mut framebuffer = Framebuffer::new(2, 2);
+        framebuffer.pixels = vec![
+            Color::new(1.0, 2.0, 3.0),
+            Color::new(0.5, 0.25, 100.0),
+            Color::new(-1.0, 0.0, 0.0),
+            Color::new(7.0, 8.0, 9.0),
+        ];
+
+        let path = std::env::temp_dir().join(format!("rt_test_{}.pfm", std::process::id()));
+        write(&path, &framebuffer, &Options::default()).unwrap();
+        let image = crate::image::Image::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixels, framebuffer.pixels);
+    }
+}
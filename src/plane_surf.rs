@@ -1,3 +1,4 @@
+use crate::aabb::Aabb;
 use crate::hit::*;
 use crate::material::Material;
 use crate::ray::Ray;
@@ -22,10 +23,34 @@ impl Plane {
             material,
         }
     }
+
+    /// Unit directions of u and v on the plane. u follows +x on floors and walls and v goes up walls,
+    /// or away from the viewer on floors
+    fn tangents(&self) -> (Vec3, Vec3) {
+        let normal = -Vec3::unit_vector(&self.normal);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let tangent = if Vec3::cross(&up, &normal).length() > 1e-6 {
+            Vec3::unit_vector(&Vec3::cross(&up, &normal))
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+
+        (tangent, Vec3::cross(&normal, &tangent))
+    }
+
+    /// Position on the plane, 0.0-1.0 over its width and height
+    fn uv(&self, point: &Vec3) -> (f64, f64) {
+        let (tangent, bitangent) = self.tangents();
+
+        (
+            Vec3::dot(point, &tangent) / self.width + 0.5,
+            Vec3::dot(point, &bitangent) / self.height + 0.5,
+        )
+    }
 }
 
 impl Hittable for Plane {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let denom = Vec3::dot(&self.normal, &r.direction);
         if denom > 1e-6 {
             let t = (-self.dist - Vec3::dot(&self.normal, &r.origin)) / denom;
@@ -35,13 +60,22 @@ impl Hittable for Plane {
                 let d = point.x().abs().max(point.y().abs().max(point.z().abs()));
 
                 if d < self.width / 2.0 && d < self.height / 2.0 {
+                    let (u, v) = self.uv(&point);
+                    let (tangent, bitangent) = self.tangents();
+                    // Only the side facing the way of the configured normal can be hit
+                    let normal = -Vec3::unit_vector(&self.normal);
+
                     return Some(HitRecord {
                         t,
                         point,
-                        normal: self.normal,
-                        u: 0.0,
-                        v: 0.0,
-                        material: self.material,
+                        normal,
+                        geometric_normal: normal,
+                        u,
+                        v,
+                        dpdu: self.width * tangent,
+                        dpdv: self.height * bitangent,
+                        color: None,
+                        material: &self.material,
                     });
                 }
             }
@@ -49,4 +83,10 @@ impl Hittable for Plane {
 
         None
     }
+
+    fn bounding_box(&self) -> Aabb {
+        // Hits are only accepted within a cube around the origin
+        let half = self.width.min(self.height) / 2.0;
+        Aabb::new(Vec3::new(-half, -half, -half), Vec3::new(half, half, half))
+    }
 }
@@ -0,0 +1,420 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::mesh::{self, MeshData};
+use crate::tone_map::srgb_eotf;
+use crate::vec3::Vec3;
+
+#[derive(Debug)]
+pub enum Error {
+    FailedToRead(std::io::Error),
+    InvalidHeader(&'static str),
+    InvalidData(&'static str),
+    InvalidMesh(mesh::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::FailedToRead(e) => write!(f, "failed to read PLY file: {e}"),
+            Error::InvalidHeader(reason) => write!(f, "invalid PLY header: {reason}"),
+            Error::InvalidData(reason) => write!(f, "invalid PLY data: {reason}"),
+            Error::InvalidMesh(e) => write!(f, "invalid PLY model: {e}"),
+        }
+    }
+}
+
+/// Stanford PLY model, loaded when the config is read
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "PathBuf", into = "PathBuf")]
+pub struct PlyFile {
+    path: PathBuf,
+    pub data: Arc<MeshData>,
+}
+
+impl TryFrom<PathBuf> for PlyFile {
+    type Error = Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        let data = std::fs::read(&path).map_err(Error::FailedToRead)?;
+
+        Ok(PlyFile {
+            path,
+            data: Arc::new(decode(&data)?),
+        })
+    }
+}
+
+impl From<PlyFile> for PathBuf {
+    fn from(file: PlyFile) -> Self {
+        file.path
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(Scalar),
+    /// Count followed by that many items
+    List(Scalar, Scalar),
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|(name, _)| names.contains(&name.as_str()))
+    }
+}
+
+/// Splits the header off the data, returning the format, the elements and where the body starts
+fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, usize), Error> {
+    const END: &[u8] = b"end_header";
+    let end = data
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or(Error::InvalidHeader("no end_header"))?;
+    let body = data[end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(data.len(), |i| end + i + 1);
+    let header = std::str::from_utf8(&data[..end]).map_err(|_| Error::InvalidHeader("header is not text"))?;
+
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err(Error::InvalidHeader("not a PLY file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens[..] {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::LittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BigEndian),
+            ["format", ..] => return Err(Error::InvalidHeader("unknown format")),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| Error::InvalidHeader("invalid element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let property = match (Scalar::parse(count), Scalar::parse(item)) {
+                    (Some(count), Some(item)) => Property::List(count, item),
+                    _ => return Err(Error::InvalidHeader("unknown property type")),
+                };
+                let element = elements.last_mut().ok_or(Error::InvalidHeader("property outside of an element"))?;
+                element.properties.push((name.to_string(), property));
+            }
+            ["property", ty, name] => {
+                let property = Property::Scalar(Scalar::parse(ty).ok_or(Error::InvalidHeader("unknown property type"))?);
+                let element = elements.last_mut().ok_or(Error::InvalidHeader("property outside of an element"))?;
+                element.properties.push((name.to_string(), property));
+            }
+            _ => {}
+        }
+    }
+
+    Ok((format.ok_or(Error::InvalidHeader("no format"))?, elements, body))
+}
+
+/// Reads the values of the body one by one, whatever the format
+struct Body<'a> {
+    data: &'a [u8],
+    pos: usize,
+    format: Format,
+}
+
+impl Body<'_> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + N)
+            .ok_or(Error::InvalidData("file ends early"))?;
+        self.pos += N;
+
+        let mut array: [u8; N] = bytes.try_into().unwrap();
+        if self.format == Format::BigEndian {
+            array.reverse();
+        }
+        Ok(array)
+    }
+
+    fn read(&mut self, ty: Scalar) -> Result<f64, Error> {
+        if self.format == Format::Ascii {
+            while self.pos < self.data.len() && self.data[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+            let start = self.pos;
+            while self.pos < self.data.len() && !self.data[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+
+            return std::str::from_utf8(&self.data[start..self.pos])
+                .ok()
+                .and_then(|token| token.parse().ok())
+                .ok_or(Error::InvalidData("invalid number"));
+        }
+
+        // Bytes are put in little endian order by `bytes`
+        Ok(match ty {
+            Scalar::I8 => i8::from_le_bytes(self.bytes()?) as f64,
+            Scalar::U8 => u8::from_le_bytes(self.bytes()?) as f64,
+            Scalar::I16 => i16::from_le_bytes(self.bytes()?) as f64,
+            Scalar::U16 => u16::from_le_bytes(self.bytes()?) as f64,
+            Scalar::I32 => i32::from_le_bytes(self.bytes()?) as f64,
+            Scalar::U32 => u32::from_le_bytes(self.bytes()?) as f64,
+            Scalar::F32 => f32::from_le_bytes(self.bytes()?) as f64,
+            Scalar::F64 => f64::from_le_bytes(self.bytes()?),
+        })
+    }
+
+    /// Values of every property of one element. Lists put their length in `values` and their items in `lists`
+    fn read_element(&mut self, element: &Element, values: &mut Vec<f64>, lists: &mut Vec<Vec<f64>>) -> Result<(), Error> {
+        values.clear();
+        lists.clear();
+        for (_, property) in &element.properties {
+            match *property {
+                Property::Scalar(ty) => values.push(self.read(ty)?),
+                Property::List(count, item) => {
+                    let count = self.read(count)?;
+                    if count < 0.0 {
+                        return Err(Error::InvalidData("negative list length"));
+                    }
+                    let items = (0..count as usize).map(|_| self.read(item)).collect::<Result<_, _>>()?;
+                    values.push(count);
+                    lists.push(items);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Colour channel as a linear value; 8-bit channels are sRGB encoded like in images
+fn channel(value: f64, ty: &Property) -> f64 {
+    match ty {
+        Property::Scalar(Scalar::U8) => srgb_eotf(value / 255.0),
+        Property::Scalar(Scalar::U16) => value / 65535.0,
+        _ => value,
+    }
+}
+
+/// Decodes an ASCII or binary PLY file with `vertex` and `face` elements
+pub fn decode(data: &[u8]) -> Result<MeshData, Error> {
+    let (format, elements, start) = parse_header(data)?;
+    let mut body = Body { data, pos: start, format };
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut faces = Vec::new();
+    let (mut values, mut lists) = (Vec::new(), Vec::new());
+
+    for element in &elements {
+        let position = ["x", "y", "z"].map(|name| element.find(&[name]));
+        let normal = ["nx", "ny", "nz"].map(|name| element.find(&[name]));
+        let uv = [
+            element.find(&["u", "s", "texture_u", "texture_s"]),
+            element.find(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        let color = ["red", "green", "blue"].map(|name| element.find(&[name]));
+        let indices = element
+            .properties
+            .iter()
+            .filter(|(_, p)| matches!(p, Property::List(..)))
+            .position(|(name, _)| name == "vertex_indices" || name == "vertex_index");
+
+        for _ in 0..element.count {
+            body.read_element(element, &mut values, &mut lists)?;
+
+            match element.name.as_str() {
+                "vertex" => {
+                    let [Some(x), Some(y), Some(z)] = position else {
+                        return Err(Error::InvalidHeader("vertices without x, y and z"));
+                    };
+                    vertices.push(Vec3::new(values[x], values[y], values[z]));
+                    if let [Some(x), Some(y), Some(z)] = normal {
+                        normals.push(Vec3::new(values[x], values[y], values[z]));
+                    }
+                    if let [Some(u), Some(v)] = uv {
+                        uvs.push((values[u], values[v]));
+                    }
+                    if let [Some(r), Some(g), Some(b)] = color {
+                        let channel = |i: usize| channel(values[i], &element.properties[i].1);
+                        colors.push(Color::new(channel(r), channel(g), channel(b)));
+                    }
+                }
+                "face" => {
+                    let corners = &lists[indices.ok_or(Error::InvalidHeader("faces without vertex_indices"))?];
+                    if corners.len() < 3 {
+                        return Err(Error::InvalidData("face with less than three corners"));
+                    }
+                    // Indices are read like every other value, so float lists could hold anything
+                    if corners.iter().any(|&c| c < 0.0 || c.fract() != 0.0) {
+                        return Err(Error::InvalidData("vertex index is not a whole number"));
+                    }
+                    // Fan around the first corner, as for OBJ polygons
+                    for i in 1..corners.len() - 1 {
+                        faces.push([corners[0], corners[i], corners[i + 1]].map(|c| c as usize));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    MeshData::new(vertices, normals, uvs, colors, faces).map_err(Error::InvalidMesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "ply
+format FORMAT 1.0
+comment unit square in two parts, with a material element to skip
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+element material 1
+property list uchar float values
+element face 2
+property uchar flags
+property list uchar int vertex_indices
+end_header
+";
+
+    fn binary(format: &str, le: bool) -> Vec<u8> {
+        let mut data = HEADER.replace("FORMAT", format).into_bytes();
+        let float = |data: &mut Vec<u8>, f: f32| data.extend(if le { f.to_le_bytes() } else { f.to_be_bytes() });
+        let int = |data: &mut Vec<u8>, i: i32| data.extend(if le { i.to_le_bytes() } else { i.to_be_bytes() });
+
+        for (x, y) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+            for v in [x, y, 0.0, 0.0, 0.0, 1.0] {
+                float(&mut data, v);
+            }
+            data.extend([255, 0, 0]);
+        }
+        data.push(2);
+        float(&mut data, 0.5);
+        float(&mut data, 0.25);
+        data.extend([0, 3]);
+        for i in [0, 1, 2] {
+            int(&mut data, i);
+        }
+        data.extend([0, 3]);
+        for i in [0, 2, 3] {
+            int(&mut data, i);
+        }
+
+        data
+    }
+
+    #[test]
+    fn test_formats() {
+        let ascii = HEADER.replace("FORMAT", "ascii")
+            + "0 0 0 0 0 1 255 0 0\n1 0 0 0 0 1 255 0 0\n1 1 0 0 0 1 255 0 0\n0 1 0 0 0 1 255 0 0\n"
+            + "2 0.5 0.25\n"
+            + "0 3 0 1 2\n0 3 0 2 3\n";
+        let ascii = decode(ascii.as_bytes()).unwrap();
+
+        for data in [binary("binary_little_endian", true), binary("binary_big_endian", false)] {
+            assert_eq!(decode(&data).unwrap(), ascii);
+        }
+
+        assert_eq!(ascii.vertices[2], Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(ascii.normals, vec![Vec3::new(0.0, 0.0, 1.0); 4]);
+        assert_eq!(ascii.colors, vec![Color::new(1.0, 0.0, 0.0); 4]);
+        assert!(ascii.uvs.is_empty());
+        assert_eq!(ascii.faces, [[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn test_polygons() {
+        let data = "ply\nformat ascii 1.0\nelement vertex 5\nproperty double x\nproperty double y\nproperty double z\n\
+                    property float s\nproperty float t\nelement face 1\nproperty list uchar uint vertex_index\nend_header\n\
+                    0 0 0 0 0\n1 0 0 1 0\n2 1 0 1 1\n1 2 0 0 1\n0 1 0 0 0\n5 0 1 2 3 4\n";
+        let mesh = decode(data.as_bytes()).unwrap();
+
+        assert_eq!(mesh.faces, [[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+        assert_eq!(mesh.uvs[2], (1.0, 1.0));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let error = |data: &str| decode(data.as_bytes()).unwrap_err().to_string();
+
+        assert_eq!(error("solid cube\n"), "invalid PLY header: no end_header");
+        assert_eq!(error("ply\nformat binary_middle_endian 1.0\nend_header\n"), "invalid PLY header: unknown format");
+        assert_eq!(
+            error("ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n"),
+            "invalid PLY data: invalid number"
+        );
+        assert_eq!(
+            error("ply\nformat ascii 1.0\nelement vertex 0\nproperty float x\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n3 0 1 2\n"),
+            "invalid PLY model: face 0 refers to vertex 0, which doesn't exist"
+        );
+
+        // Negative and fractional indices aren't rounded onto some other vertex
+        let faces = "ply\nformat ascii 1.0\nelement vertex 0\nproperty float x\nelement face 1\nproperty list uchar TYPE vertex_indices\nend_header\n";
+        for (kind, face) in [("int", "3 0 -1 2\n"), ("float", "3 0 2.7 1\n")] {
+            assert_eq!(
+                error(&(faces.replace("TYPE", kind) + face)),
+                "invalid PLY data: vertex index is not a whole number"
+            );
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+
+use crate::color::Color;
+use crate::config::Application;
+use crate::hit::HitRecord;
+use crate::material::{apply_bump, emitted, scatter, Material};
+use crate::ray::Ray;
+use crate::sampling::mix;
+use crate::vec3::Vec3;
+
+const TILE_SIZE: u32 = 32; // Width and height of a tile in pixels
+const MAX_DEPTH: i32 = 50; // Max amount of bounces per camera ray
+
+/// Light arriving at a diffuse surface straight from an importance sampled background.
+/// `None` if the background can't be sampled, in which case it is only found by scattered rays
+fn sample_background(r: &Ray, rec: &HitRecord, app: &Application, rng: &mut impl Rng) -> Option<Color> {
+    let Material::Lambertian { albedo } = rec.material else {
+        return None;
+    };
+    let (direction, radiance, pdf) = app.background.sample(rng)?;
+
+    let cosine = Vec3::dot(&rec.facing_normal(r), &direction);
+    let shadow = rec.spawn_ray(direction);
+
+    if cosine <= 0.0 || app.world.hit(&shadow, 0.0, f64::MAX).is_some() {
+        return Some(Color::default());
+    }
+
+    Some(albedo.value_at(rec) * radiance * (cosine / (PI * pdf)))
+}
+
+/// `background_sampled` is set when the previous bounce already added the light from the background
+fn color(r: &Ray, app: &Application, depth: i32, background_sampled: bool, rng: &mut impl Rng) -> Color {
+    shade(r, app.world.hit(r, 0.0, f64::MAX), app, depth, background_sampled, rng)
+}
+
+/// Light carried back along `r`, given what it hit
+fn shade(
+    r: &Ray,
+    hit: Option<HitRecord>,
+    app: &Application,
+    depth: i32,
+    background_sampled: bool,
+    rng: &mut impl Rng,
+) -> Color {
+    if let Some(rec) = hit {
+        let rec = apply_bump(rec);
+        let emitted = emitted(rec.material, &rec);
+
+        if depth < MAX_DEPTH {
+            if let Some((attenuation, scattered)) = scatter(rec.material, r, &rec, rng) {
+                let direct = sample_background(r, &rec, app, rng);
+                let indirect = color(&scattered, app, depth + 1, direct.is_some(), rng);
+
+                return emitted + direct.unwrap_or_default() + attenuation * indirect;
+            }
+        }
+
+        emitted
+    } else if background_sampled {
+        Color::default()
+    } else {
+        app.background.color(&r.direction)
+    }
+}
+
+/// Rectangular part of the image, in framebuffer coordinates (row 0 is the top of the image)
+#[derive(Debug, Clone, Copy)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+fn tiles(width: u32, height: u32) -> Vec<Tile> {
+    (0..height)
+        .step_by(TILE_SIZE as usize)
+        .flat_map(|y| {
+            (0..width).step_by(TILE_SIZE as usize).map(move |x| Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(width - x),
+                height: TILE_SIZE.min(height - y),
+            })
+        })
+        .collect()
+}
+
+/// Averages over the samples of one pixel, as they are put into the framebuffer
+#[derive(Debug, Clone, Copy)]
+struct Pixel {
+    color: Color,
+    alpha: f64,
+    background: Color,
+}
+
+/// Averaged radiance of every pixel, stored row by row from the top of the image.
+/// `alpha` holds the share of each pixel's samples that hit an object, and `background` the part of
+/// the radiance that came from the samples that missed
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+    pub alpha: Vec<f64>,
+    pub background: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::default(); (width * height) as usize],
+            alpha: vec![1.0; (width * height) as usize],
+            background: vec![Color::default(); (width * height) as usize],
+        }
+    }
+
+    /// Radiance of the objects alone, premultiplied by the alpha
+    pub fn foreground(&self, index: usize) -> Color {
+        self.pixels[index] - self.background[index]
+    }
+
+    /// Pixels as rows from the top of the image to the bottom
+    pub fn rows(&self) -> std::slice::Chunks<'_, Color> {
+        self.pixels.chunks(self.width as usize)
+    }
+
+    fn blit(&mut self, tile: Tile, pixels: &[Pixel]) {
+        for (row, line) in pixels.chunks(tile.width as usize).enumerate() {
+            let start = ((tile.y + row as u32) * self.width + tile.x) as usize;
+
+            for (i, pixel) in line.iter().enumerate() {
+                self.pixels[start + i] = pixel.color;
+                self.alpha[start + i] = pixel.alpha;
+                self.background[start + i] = pixel.background;
+            }
+        }
+    }
+}
+
+/// Every sample gets its own generator, so the image only depends on the seed and not on
+/// which thread rendered which tile
+fn sample_rng(seed: u64, x: u32, y: u32, sample: i32) -> SmallRng {
+    let pixel = (y as u64) << 32 | x as u64;
+    SmallRng::seed_from_u64(mix(mix(seed ^ mix(pixel)) ^ sample as u64))
+}
+
+fn render_tile(app: &Application, tile: Tile) -> Vec<Pixel> {
+    let mut pixels = Vec::with_capacity((tile.width * tile.height) as usize);
+
+    for y in tile.y..tile.y + tile.height {
+        // Camera coordinates go from the bottom of the image upwards
+        let j = app.height - 1 - y;
+
+        for i in tile.x..tile.x + tile.width {
+            let mut col = Color::default();
+            let mut covered = 0;
+            let mut missed = Color::default();
+
+            for sample in 0..app.samples {
+                let rng = &mut sample_rng(app.seed, i, y, sample);
+                let u = (i as f64 + rng.gen::<f64>()) / app.width as f64;
+                let v = (j as f64 + rng.gen::<f64>()) / app.height as f64;
+                let r = &app.camera.get_ray(u, v, rng);
+                let hit = app.world.hit(r, 0.0, f64::MAX);
+
+                let c = shade(r, hit, app, 1, false, rng);
+                if hit.is_some() {
+                    covered += 1;
+                } else {
+                    missed += c;
+                }
+                col += c;
+            }
+
+            pixels.push(Pixel {
+                color: col / app.samples as f64,
+                alpha: covered as f64 / app.samples as f64,
+                background: missed / app.samples as f64,
+            });
+        }
+    }
+
+    pixels
+}
+
+/// Renders the scene by splitting the image into tiles which are picked up by `app.threads` workers
+pub fn render(app: &Application) -> Framebuffer {
+    let tiles = tiles(app.width, app.height);
+    let next_tile = AtomicUsize::new(0);
+    let finished = AtomicUsize::new(0);
+    let debug_pad = tiles.len().to_string().len();
+
+    let rendered: Vec<(Tile, Vec<Pixel>)> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..app.threads.max(1))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+
+                    loop {
+                        let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                        let Some(&tile) = tiles.get(index) else {
+                            break;
+                        };
+
+                        done.push((tile, render_tile(app, tile)));
+
+                        let remaining = tiles.len() - finished.fetch_add(1, Ordering::Relaxed) - 1;
+                        eprint!("\rTiles remaining: {remaining: <debug_pad$}");
+                    }
+
+                    done
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Render worker panicked"))
+            .collect()
+    });
+
+    let mut framebuffer = Framebuffer::new(app.width, app.height);
+    for (tile, pixels) in rendered {
+        framebuffer.blit(tile, &pixels);
+    }
+
+    framebuffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::Background;
+    use crate::camera::Camera;
+    use crate::hit::HittableList;
+    use crate::sphere::Sphere;
+    use crate::tone_map::ToneMap;
+
+    /// Mirror sphere in front of the origin, with a red lamp behind the origin that is only visible in the mirror
+    fn mirror_scene(fuzz: f64) -> Application {
+        let mirror = Material::Metal {
+            albedo: Color::new(0.8, 0.8, 0.8).into(),
+            fuzz,
+        };
+        let lamp = Material::DiffuseLight {
+            emit: Color::new(1.0, 0.0, 0.0).into(),
+            strength: 1.0,
+        };
+
+        Application {
+            world: Box::new(HittableList::new(vec![
+                Box::new(Sphere::new(Vec3::new(0.0, 0.0, -2.0), 1.0, mirror)),
+                Box::new(Sphere::new(Vec3::new(0.0, 0.0, 3.0), 1.0, lamp)),
+            ])),
+            camera: Camera::new(
+                Vec3::default(),
+                Vec3::new(0.0, 0.0, -1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                40.0,
+                0.0,
+                1,
+                1,
+            ),
+            background: Background::Solid(Color::new(0.0, 0.0, 1.0)),
+            exposure: 0.0,
+            tone_map: ToneMap::default(),
+            samples: 1,
+            height: 1,
+            width: 1,
+            threads: 1,
+            seed: 0,
+        }
+    }
+
+    #[test]
+    fn test_tiles() {
+        let (width, height) = (TILE_SIZE * 2 + 5, TILE_SIZE + 1);
+        let tiles = tiles(width, height);
+        assert_eq!(tiles.len(), 6);
+
+        // The last column and row of tiles only cover what's left of the image
+        let last = tiles[tiles.len() - 1];
+        assert_eq!((last.x, last.y, last.width, last.height), (TILE_SIZE * 2, TILE_SIZE, 5, 1));
+
+        // Every pixel is in exactly one tile
+        let mut covered = vec![0; (width * height) as usize];
+        for tile in &tiles {
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[(y * width + x) as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&n| n == 1));
+
+        assert!(super::tiles(0, 0).is_empty());
+    }
+
+    #[test]
+    fn test_blit() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        let tile = Tile {
+            x: 2,
+            y: 1,
+            width: 2,
+            height: 2,
+        };
+        let pixels: Vec<_> = (0..4)
+            .map(|i| Pixel {
+                color: Color::new(i as f64, 0.0, 0.0),
+                alpha: 0.5,
+                background: Color::default(),
+            })
+            .collect();
+        framebuffer.blit(tile, &pixels);
+
+        let reds: Vec<Vec<f64>> = framebuffer.rows().map(|row| row.iter().map(|c| c.r()).collect()).collect();
+        assert_eq!(reds, [[0.0, 0.0, 0.0, 0.0], [0.0, 0.0, 0.0, 1.0], [0.0, 0.0, 2.0, 3.0]]);
+        assert_eq!(framebuffer.alpha, [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.5, 0.5, 1.0, 1.0, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        // Big enough for several tiles, with rough reflections that use plenty of random numbers
+        let render_with = |threads| {
+            let app = Application {
+                width: TILE_SIZE * 2 + 3,
+                height: TILE_SIZE + 5,
+                samples: 2,
+                threads,
+                seed: 7,
+                ..mirror_scene(0.5)
+            };
+            render(&app)
+        };
+
+        let single = render_with(1);
+        let parallel = render_with(4);
+        assert_eq!(single.pixels, parallel.pixels);
+        assert_eq!(single.alpha, parallel.alpha);
+        assert_eq!(single.background, parallel.background);
+        assert!(single.pixels.iter().any(|p| p.r() > 0.0 && p.r() < 0.8));
+    }
+
+    #[test]
+    fn test_metal_reflects_surroundings() {
+        let app = mirror_scene(0.0);
+        let mut rng = sample_rng(app.seed, 0, 0, 0);
+
+        // Straight at the mirror, which reflects the ray back into the lamp
+        let c = color(&Ray::new(Vec3::default(), Vec3::new(0.0, 0.0, -1.0)), &app, 0, false, &mut rng);
+        assert!((c - Color::new(0.8, 0.0, 0.0)).length() < 1e-9, "{c:?}");
+
+        // Grazing the edge of the mirror, the reflection goes past the lamp into the background
+        let c = color(&Ray::new(Vec3::default(), Vec3::new(0.0, 0.49, -1.0)), &app, 0, false, &mut rng);
+        assert!((c - Color::new(0.0, 0.0, 0.8)).length() < 1e-9, "{c:?}");
+    }
+
+    #[test]
+    fn test_fuzzy_metal_stays_above_surface() {
+        let app = mirror_scene(1.0);
+        let r = Ray::new(Vec3::default(), Vec3::new(0.0, 0.3, -1.0));
+        let rec = app.world.hit(&r, 0.0, f64::MAX).unwrap();
+        let normal = rec.facing_normal(&r);
+        let mut rng = sample_rng(app.seed, 0, 0, 0);
+
+        let mut scattered = 0;
+        for _ in 0..1000 {
+            if let Some((attenuation, ray)) = scatter(rec.material, &r, &rec, &mut rng) {
+                assert_eq!(attenuation, Color::new(0.8, 0.8, 0.8));
+                assert!(Vec3::dot(&ray.direction, &normal) > 0.0);
+                scattered += 1;
+            }
+        }
+
+        // Most of the rough reflections survive, but not all of them
+        assert!(scattered > 500 && scattered < 1000, "{scattered}");
+    }
+}
@@ -0,0 +1,227 @@
+use std::f64::consts::PI;
+
+use rand::prelude::*;
+
+use crate::vec3::Vec3;
+
+/// Finalizer of the SplitMix64 generator, spreads similar inputs over the whole range
+pub fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Uniformly distributed point inside the unit sphere
+pub fn random_in_unit_sphere(rng: &mut impl Rng) -> Vec3 {
+    loop {
+        let p = 2.0 * Vec3::new(rng.gen(), rng.gen(), rng.gen()) - Vec3::new(1.0, 1.0, 1.0);
+
+        if p.squared_length() < 1.0 {
+            return p;
+        }
+    }
+}
+
+/// Uniformly distributed direction
+pub fn random_unit_vector(rng: &mut impl Rng) -> Vec3 {
+    let z: f64 = rng.gen_range(-1.0..1.0);
+    let phi = 2.0 * PI * rng.gen::<f64>();
+    let r = (1.0 - z * z).sqrt();
+
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Uniformly distributed point inside the unit disk on the xy plane
+pub fn random_in_unit_disk(rng: &mut impl Rng) -> Vec3 {
+    loop {
+        let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
+
+        if p.squared_length() < 1.0 {
+            return p;
+        }
+    }
+}
+
+/// Direction in the hemisphere around +z, with density cos(theta) / pi
+pub fn cosine_hemisphere(rng: &mut impl Rng) -> Vec3 {
+    let r1: f64 = rng.gen();
+    let r2: f64 = rng.gen();
+    let phi = 2.0 * PI * r1;
+    let r = r2.sqrt();
+
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - r2).sqrt())
+}
+
+/// Orthonormal basis with `w` along a given direction, used to move samples from local to world space
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(n: &Vec3) -> Onb {
+        let w = Vec3::unit_vector(n);
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::unit_vector(&Vec3::cross(&w, &a));
+        let u = Vec3::cross(&w, &v);
+
+        Onb { u, v, w }
+    }
+
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+
+    /// Inverse of `local`, expresses a world space vector in the basis
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(Vec3::dot(a, &self.u), Vec3::dot(a, &self.v), Vec3::dot(a, &self.w))
+    }
+}
+
+/// Direction in the hemisphere around `normal`, with density cos(theta) / pi
+pub fn cosine_weighted(normal: &Vec3, rng: &mut impl Rng) -> Vec3 {
+    Onb::from_w(normal).local(&cosine_hemisphere(rng))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+
+    const N: usize = 100_000;
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(7)
+    }
+
+    fn mean(samples: &[f64]) -> f64 {
+        samples.iter().sum::<f64>() / samples.len() as f64
+    }
+
+    /// Pearson's chi-squared statistic of the samples against a uniform distribution over [0, 1)
+    fn chi_squared_uniform(samples: &[f64], bins: usize) -> f64 {
+        let mut counts = vec![0.0; bins];
+        for &s in samples {
+            counts[((s * bins as f64) as usize).min(bins - 1)] += 1.0;
+        }
+
+        let expected = samples.len() as f64 / bins as f64;
+        counts.iter().map(|c| (c - expected).powi(2) / expected).sum()
+    }
+
+    // 99.9th percentile of the chi-squared distribution with 19 degrees of freedom
+    const CHI_SQUARED_19: f64 = 43.82;
+
+    #[test]
+    fn test_random_in_unit_sphere() {
+        let mut rng = rng();
+        let points: Vec<Vec3> = (0..N).map(|_| random_in_unit_sphere(&mut rng)).collect();
+
+        assert!(points.iter().all(|p| p.length() < 1.0));
+        for axis in 0..3 {
+            let values: Vec<f64> = points.iter().map(|p| p[axis]).collect();
+            let squares: Vec<f64> = values.iter().map(|v| v * v).collect();
+            assert!(mean(&values).abs() < 0.01);
+            assert!((mean(&squares) - 0.2).abs() < 0.01);
+        }
+
+        // The cube of the distance from the centre is uniform for points spread evenly through the volume
+        let radii: Vec<f64> = points.iter().map(|p| p.length().powi(3)).collect();
+        assert!(chi_squared_uniform(&radii, 20) < CHI_SQUARED_19);
+    }
+
+    #[test]
+    fn test_random_unit_vector() {
+        let mut rng = rng();
+        let points: Vec<Vec3> = (0..N).map(|_| random_unit_vector(&mut rng)).collect();
+
+        assert!(points.iter().all(|p| (p.length() - 1.0).abs() < 1e-9));
+        for axis in 0..3 {
+            let values: Vec<f64> = points.iter().map(|p| p[axis]).collect();
+            let squares: Vec<f64> = values.iter().map(|v| v * v).collect();
+            assert!(mean(&values).abs() < 0.01);
+            assert!((mean(&squares) - 1.0 / 3.0).abs() < 0.01);
+        }
+
+        // Every coordinate of a uniform direction is uniform over [-1, 1]
+        let xs: Vec<f64> = points.iter().map(|p| 0.5 * (p.x() + 1.0)).collect();
+        assert!(chi_squared_uniform(&xs, 20) < CHI_SQUARED_19);
+    }
+
+    #[test]
+    fn test_random_in_unit_disk() {
+        let mut rng = rng();
+        let points: Vec<Vec3> = (0..N).map(|_| random_in_unit_disk(&mut rng)).collect();
+
+        assert!(points.iter().all(|p| p.z() == 0.0 && p.length() < 1.0));
+        let xs: Vec<f64> = points.iter().map(|p| p.x()).collect();
+        let ys: Vec<f64> = points.iter().map(|p| p.y()).collect();
+        assert!(mean(&xs).abs() < 0.01 && mean(&ys).abs() < 0.01);
+
+        let radii: Vec<f64> = points.iter().map(|p| p.squared_length()).collect();
+        assert!(chi_squared_uniform(&radii, 20) < CHI_SQUARED_19);
+    }
+
+    #[test]
+    fn test_cosine_hemisphere() {
+        let mut rng = rng();
+        let directions: Vec<Vec3> = (0..N).map(|_| cosine_hemisphere(&mut rng)).collect();
+
+        assert!(directions.iter().all(|d| d.z() >= 0.0 && (d.length() - 1.0).abs() < 1e-9));
+
+        // With density cos(theta) / pi, E[cos] = 2/3 and cos^2 is uniform
+        let cosines: Vec<f64> = directions.iter().map(|d| d.z()).collect();
+        assert!((mean(&cosines) - 2.0 / 3.0).abs() < 0.01);
+
+        let squares: Vec<f64> = cosines.iter().map(|c| c * c).collect();
+        assert!(chi_squared_uniform(&squares, 20) < CHI_SQUARED_19);
+
+        // The azimuth is uniform
+        let azimuths: Vec<f64> = directions
+            .iter()
+            .map(|d| (d.y().atan2(d.x()) + PI) / (2.0 * PI))
+            .collect();
+        assert!(chi_squared_uniform(&azimuths, 20) < CHI_SQUARED_19);
+    }
+
+    #[test]
+    fn test_onb() {
+        for n in [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.3, -2.0, 0.5),
+        ] {
+            let onb = Onb::from_w(&n);
+            for (a, b) in [(onb.u, onb.v), (onb.v, onb.w), (onb.w, onb.u)] {
+                assert!(Vec3::dot(&a, &b).abs() < 1e-9);
+            }
+            for axis in [onb.u, onb.v, onb.w] {
+                assert!((axis.length() - 1.0).abs() < 1e-9);
+            }
+            assert!((onb.w - Vec3::unit_vector(&n)).length() < 1e-9);
+
+            let a = Vec3::new(0.2, -0.7, 1.3);
+            assert!((onb.local(&onb.to_local(&a)) - a).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_cosine_weighted() {
+        let mut rng = rng();
+        let normal = Vec3::new(-1.0, 2.0, 0.5);
+        let unit_normal = Vec3::unit_vector(&normal);
+
+        let cosines: Vec<f64> = (0..N)
+            .map(|_| Vec3::dot(&cosine_weighted(&normal, &mut rng), &unit_normal))
+            .collect();
+
+        assert!(cosines.iter().all(|&c| c >= 0.0));
+        assert!((mean(&cosines) - 2.0 / 3.0).abs() < 0.01);
+    }
+}
@@ -1,3 +1,6 @@
+use std::f64::consts::PI;
+
+use crate::aabb::Aabb;
 use crate::hit::*;
 use crate::material::Material;
 use crate::ray::Ray;
@@ -18,43 +21,131 @@ impl Sphere {
             material,
         }
     }
+
+    /// Longitude and latitude of a point on the sphere, u starts at -x and v goes from the bottom to the top
+    fn uv(&self, point: &Vec3) -> (f64, f64) {
+        let p = (*point - self.center) / self.radius;
+        let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    /// Derivatives of the point along u and v, following `uv`. Both vanish at the poles
+    fn tangents(&self, point: &Vec3) -> (Vec3, Vec3) {
+        let p = *point - self.center;
+        let rho = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        let dpdu = 2.0 * PI * Vec3::new(p.z(), 0.0, -p.x());
+
+        if rho < 1e-12 {
+            return (dpdu, Vec3::default());
+        }
+
+        (dpdu, PI * Vec3::new(-p.y() * p.x() / rho, rho, -p.y() * p.z() / rho))
+    }
 }
 
-impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+impl Sphere {
+    /// Where the line of the ray goes in and out of the sphere, nearest first
+    fn roots(&self, r: &Ray) -> Option<(f64, f64)> {
         let oc = r.origin - self.center;
         let a = Vec3::dot(&r.direction, &r.direction);
         let b = Vec3::dot(&oc, &r.direction);
         let c = Vec3::dot(&oc, &oc) - self.radius * self.radius;
 
         let discriminant = b * b - a * c;
+        if discriminant <= 0.0 {
+            return None;
+        }
+
+        Some(((-b - discriminant.sqrt()) / a, (-b + discriminant.sqrt()) / a))
+    }
 
-        if discriminant > 0.0 {
-            let mut temp = (-b - discriminant.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                return Some(HitRecord {
-                    t: temp,
-                    point: r.at(temp),
-                    normal: (r.at(temp) - self.center) / self.radius,
-                    u: 0.0,
-                    v: 0.0,
-                    material: self.material,
-                });
-            }
-
-            temp = (-b + discriminant.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                return Some(HitRecord {
-                    t: temp,
-                    point: r.at(temp),
-                    normal: (r.at(temp) - self.center) / self.radius,
-                    u: 0.0,
-                    v: 0.0,
-                    material: self.material,
-                });
-            }
+    fn record(&self, r: &Ray, t: f64) -> HitRecord<'_> {
+        let point = r.at(t);
+        let normal = (point - self.center) / self.radius;
+        let (u, v) = self.uv(&point);
+        let (dpdu, dpdv) = self.tangents(&point);
+
+        HitRecord {
+            t,
+            point,
+            normal,
+            geometric_normal: Vec3::unit_vector(&normal),
+            u,
+            v,
+            dpdu,
+            dpdv,
+            color: None,
+            material: &self.material,
         }
+    }
+}
 
-        None
+impl Hittable for Sphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (near, far) = self.roots(r)?;
+
+        [near, far]
+            .into_iter()
+            .find(|&t| t < t_max && t > t_min)
+            .map(|t| self.record(r, t))
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, r: &Ray) -> Option<Vec<Interval<'_>>> {
+        Some(
+            self.roots(r)
+                .map(|(near, far)| Interval {
+                    enter: self.record(r, near),
+                    exit: self.record(r, far),
+                })
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let radius = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - radius, self.center + radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uv() {
+        let sphere = Sphere::new(Vec3::new(1.0, 2.0, 3.0), 2.0, Material::default());
+        let uv = |p: Vec3| sphere.uv(&(sphere.center + 2.0 * p));
+
+        let (u, v) = uv(Vec3::new(0.0, 0.0, 1.0));
+        assert!((u - 0.25).abs() < 1e-9 && (v - 0.5).abs() < 1e-9);
+        let (u, _) = uv(Vec3::new(1.0, 0.0, 0.0));
+        assert!((u - 0.5).abs() < 1e-9);
+        assert!(uv(Vec3::new(0.0, -1.0, 0.0)).1.abs() < 1e-9);
+        assert!((uv(Vec3::new(0.0, 1.0, 0.0)).1 - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tangents() {
+        let sphere = Sphere::new(Vec3::new(1.0, 2.0, 3.0), 2.0, Material::default());
+        let h = 1e-6;
+
+        for direction in [Vec3::new(0.3, 0.5, 0.8), Vec3::new(-0.9, -0.2, 0.1), Vec3::new(0.1, 0.9, -0.6)] {
+            let point = sphere.center + 2.0 * Vec3::unit_vector(&direction);
+            let (dpdu, dpdv) = sphere.tangents(&point);
+
+            // Moving along a tangent changes u or v at the expected rate and leaves the other alone
+            let (u0, v0) = sphere.uv(&point);
+            let (u1, v1) = sphere.uv(&(point + h * dpdu));
+            assert!(((u1 - u0) / h - 1.0).abs() < 1e-3 && ((v1 - v0) / h).abs() < 1e-3);
+            let (u1, v1) = sphere.uv(&(point + h * dpdv));
+            assert!(((u1 - u0) / h).abs() < 1e-3 && ((v1 - v0) / h - 1.0).abs() < 1e-3);
+        }
     }
 }
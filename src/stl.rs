@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mesh::{self, MeshData};
+use crate::vec3::Vec3;
+
+#[derive(Debug)]
+pub enum Error {
+    FailedToRead(std::io::Error),
+    InvalidData(&'static str),
+    InvalidMesh(mesh::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::FailedToRead(e) => write!(f, "failed to read STL file: {e}"),
+            Error::InvalidData(reason) => write!(f, "invalid STL data: {reason}"),
+            Error::InvalidMesh(e) => write!(f, "invalid STL model: {e}"),
+        }
+    }
+}
+
+/// STL model, loaded when the config is read
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "PathBuf", into = "PathBuf")]
+pub struct StlFile {
+    path: PathBuf,
+    pub data: Arc<MeshData>,
+}
+
+impl TryFrom<PathBuf> for StlFile {
+    type Error = Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        let data = std::fs::read(&path).map_err(Error::FailedToRead)?;
+
+        Ok(StlFile {
+            path,
+            data: Arc::new(decode(&data)?),
+        })
+    }
+}
+
+impl From<StlFile> for PathBuf {
+    fn from(file: StlFile) -> Self {
+        file.path
+    }
+}
+
+/// 80 byte header and the triangle count, then a normal, three corners and an attribute per triangle
+const BINARY_HEADER: usize = 84;
+const BINARY_TRIANGLE: usize = 50;
+
+/// Decodes an ASCII or binary STL file. STL files list every triangle with its own corners, so
+/// corners at the same position are merged into one vertex
+pub fn decode(data: &[u8]) -> Result<MeshData, Error> {
+    // Binary files may start with "solid" too, so they are recognised by their size
+    let binary = data.len() >= BINARY_HEADER
+        && data.len() == BINARY_HEADER + BINARY_TRIANGLE * u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+
+    let corners = if binary {
+        decode_binary(data)
+    } else if data.trim_ascii_start().starts_with(b"solid") {
+        decode_ascii(data)?
+    } else {
+        return Err(Error::InvalidData("neither an ASCII nor a binary STL file"));
+    };
+
+    let mut indices = HashMap::new();
+    let mut vertices = Vec::new();
+    let faces = corners
+        .chunks_exact(3)
+        .map(|triangle| {
+            [0, 1, 2].map(|i| {
+                let p = triangle[i];
+                // Adding 0.0 turns -0.0 into 0.0, so that both end up in the same vertex
+                *indices.entry([p.x(), p.y(), p.z()].map(|c| (c + 0.0).to_bits())).or_insert_with(|| {
+                    vertices.push(p);
+                    vertices.len() - 1
+                })
+            })
+        })
+        .collect();
+
+    // The normals in the file are those of the flat triangles, which the triangles work out themselves
+    MeshData::new(vertices, Vec::new(), Vec::new(), Vec::new(), faces).map_err(Error::InvalidMesh)
+}
+
+fn decode_binary(data: &[u8]) -> Vec<Vec3> {
+    data[BINARY_HEADER..]
+        .chunks_exact(BINARY_TRIANGLE)
+        .flat_map(|triangle| {
+            let float = |i: usize| f32::from_le_bytes(triangle[4 * i..4 * i + 4].try_into().unwrap()) as f64;
+            // Skip the normal
+            [1, 2, 3].map(|corner| Vec3::new(float(3 * corner), float(3 * corner + 1), float(3 * corner + 2)))
+        })
+        .collect()
+}
+
+fn decode_ascii(data: &[u8]) -> Result<Vec<Vec3>, Error> {
+    let text = std::str::from_utf8(data).map_err(|_| Error::InvalidData("file is not text"))?;
+    let mut tokens = text.split_whitespace();
+    let mut corners = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        if token == "vertex" {
+            let mut number = || {
+                tokens
+                    .next()
+                    .and_then(|t| t.parse().ok())
+                    .ok_or(Error::InvalidData("invalid vertex"))
+            };
+            corners.push(Vec3::new(number()?, number()?, number()?));
+        }
+    }
+
+    if corners.len() % 3 != 0 {
+        return Err(Error::InvalidData("facet without three vertices"));
+    }
+
+    Ok(corners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TETRAHEDRON: [[f32; 3]; 4] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    const FACES: [[usize; 3]; 4] = [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]];
+
+    /// Corners of every face, in the order of the file
+    fn corners(mesh: &MeshData) -> Vec<[Vec3; 3]> {
+        mesh.faces.iter().map(|face| face.map(|i| mesh.vertices[i])).collect()
+    }
+
+    fn expected() -> Vec<[Vec3; 3]> {
+        FACES
+            .iter()
+            .map(|face| face.map(|i| TETRAHEDRON[i].map(f64::from)).map(|[x, y, z]| Vec3::new(x, y, z)))
+            .collect()
+    }
+
+    #[test]
+    fn test_ascii() {
+        let mut text = String::from("solid tetrahedron\n");
+        for face in FACES {
+            text += "  facet normal 0 0 0\n    outer loop\n";
+            for p in face.map(|i| TETRAHEDRON[i]) {
+                text += &format!("      vertex {} {} {}\n", p[0], p[1], p[2]);
+            }
+            text += "    endloop\n  endfacet\n";
+        }
+        text += "endsolid tetrahedron\n";
+
+        let mesh = decode(text.as_bytes()).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(corners(&mesh), expected());
+        assert!(mesh.normals.is_empty());
+    }
+
+    #[test]
+    fn test_binary() {
+        // Binary files can start with "solid" as well
+        let mut data = b"solid but binary".to_vec();
+        data.resize(80, 0);
+        data.extend(4u32.to_le_bytes());
+        for face in FACES {
+            data.extend([0f32; 3].iter().flat_map(|f| f.to_le_bytes()));
+            data.extend(face.iter().flat_map(|&i| TETRAHEDRON[i]).flat_map(f32::to_le_bytes));
+            data.extend([0, 0]);
+        }
+
+        let mesh = decode(&data).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(corners(&mesh), expected());
+
+        assert!(decode(&[0; 100]).is_err());
+    }
+}
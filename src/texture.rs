@@ -0,0 +1,683 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::hit::HitRecord;
+use crate::image::{Error, Image};
+use crate::noise::{fbm, turbulence, voronoi};
+use crate::tone_map::srgb_eotf;
+use crate::vec3::Vec3;
+
+/// Colour that varies over a surface, looked up by the surface (u, v) coordinates or the point of a hit
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Texture {
+    /// Same colour everywhere; a plain `Vec3(r, g, b)` also works
+    #[serde(alias = "Vec3")]
+    Solid(f64, f64, f64),
+    /// Alternating squares of two textures, `scale` squares along each side of the (u, v) square
+    Checker {
+        even: Box<Texture>,
+        odd: Box<Texture>,
+        #[serde(default = "default_checker_scale")]
+        scale: f64,
+    },
+    /// Picture stretched over the surface, (0, 0) is its bottom left corner
+    Image {
+        path: ImageFile,
+        /// Whether the file holds sRGB encoded colours, by default true for PNG, PPM and JPEG
+        #[serde(default)]
+        srgb: Option<bool>,
+        #[serde(default)]
+        wrap: Wrap,
+        /// Wrap along v, when it differs from the one along u
+        #[serde(default)]
+        wrap_v: Option<Wrap>,
+        #[serde(default)]
+        filter: Filter,
+        /// Repetitions of the image along u and v
+        #[serde(default = "default_image_scale")]
+        scale: (f64, f64),
+        #[serde(default)]
+        offset: (f64, f64),
+    },
+    /// Shows the (u, v) coordinates as red and green, to check how a texture will be laid out
+    Uv,
+    /// Colours of the corners of a mesh blended over its triangles, white on objects without them
+    VertexColor,
+    /// Product of two textures, for example to tint an image
+    Multiply(Box<Texture>, Box<Texture>),
+    /// Pattern filling space, evaluated at the hit point so it needs no (u, v) coordinates
+    Noise {
+        #[serde(default)]
+        pattern: Pattern,
+        /// Frequency of the pattern, larger values give smaller features
+        #[serde(default = "default_noise_scale")]
+        scale: f64,
+        /// Layers of ever finer detail
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default)]
+        ramp: Ramp,
+        /// Picks a different pattern of the same kind
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+fn default_checker_scale() -> f64 {
+    10.0
+}
+
+fn default_image_scale() -> (f64, f64) {
+    (1.0, 1.0)
+}
+
+fn default_noise_scale() -> f64 {
+    1.0
+}
+
+fn default_octaves() -> u32 {
+    4
+}
+
+impl From<Color> for Texture {
+    fn from(c: Color) -> Self {
+        Texture::Solid(c.r(), c.g(), c.b())
+    }
+}
+
+impl Default for Texture {
+    fn default() -> Self {
+        Texture::Solid(0.0, 0.0, 0.0)
+    }
+}
+
+impl Texture {
+    /// Colour at the surface coordinates (`u`, `v`) of the point `p`
+    pub fn value(&self, u: f64, v: f64, p: &Vec3) -> Color {
+        self.evaluate(u, v, p, None)
+    }
+
+    /// Colour at a hit, including the vertex colours of meshes
+    pub fn value_at(&self, rec: &HitRecord) -> Color {
+        self.evaluate(rec.u, rec.v, &rec.point, rec.color)
+    }
+
+    fn evaluate(&self, u: f64, v: f64, p: &Vec3, color: Option<Color>) -> Color {
+        match self {
+            Texture::Solid(r, g, b) => Color::new(*r, *g, *b),
+            Texture::Checker { even, odd, scale } => {
+                let square = (u * scale).floor() + (v * scale).floor();
+
+                if square.rem_euclid(2.0) == 0.0 {
+                    even.evaluate(u, v, p, color)
+                } else {
+                    odd.evaluate(u, v, p, color)
+                }
+            }
+            Texture::Image {
+                path,
+                srgb,
+                wrap,
+                wrap_v,
+                filter,
+                scale,
+                offset,
+            } => path.lookup(
+                u * scale.0 + offset.0,
+                v * scale.1 + offset.1,
+                (*wrap, wrap_v.unwrap_or(*wrap)),
+                *filter,
+                srgb.unwrap_or_else(|| path.is_srgb()),
+            ),
+            Texture::Uv => Color::new(u, v, 0.0),
+            Texture::VertexColor => color.unwrap_or(Color::new(1.0, 1.0, 1.0)),
+            Texture::Multiply(a, b) => a.evaluate(u, v, p, color) * b.evaluate(u, v, p, color),
+            Texture::Noise {
+                pattern,
+                scale,
+                octaves,
+                ramp,
+                seed,
+            } => ramp.at(pattern.value(&(*p * *scale), *octaves, *seed)),
+        }
+    }
+
+    /// Reads images without an `srgb` setting as linear, for textures that hold data rather than colours
+    pub fn linear(self) -> Texture {
+        match self {
+            Texture::Checker { even, odd, scale } => Texture::Checker {
+                even: Box::new(even.linear()),
+                odd: Box::new(odd.linear()),
+                scale,
+            },
+            Texture::Multiply(a, b) => Texture::Multiply(Box::new(a.linear()), Box::new(b.linear())),
+            Texture::Image {
+                path,
+                srgb,
+                wrap,
+                wrap_v,
+                filter,
+                scale,
+                offset,
+            } => Texture::Image {
+                path,
+                srgb: Some(srgb.unwrap_or(false)),
+                wrap,
+                wrap_v,
+                filter,
+                scale,
+                offset,
+            },
+            texture => texture,
+        }
+    }
+
+    /// Loads the images of the texture that aren't loaded yet, sharing the ones in `images`
+    pub fn load(&mut self, images: &Images) -> Result<(), LoadError> {
+        match self {
+            Texture::Checker { even, odd, .. } => {
+                even.load(images)?;
+                odd.load(images)
+            }
+            Texture::Multiply(a, b) => {
+                a.load(images)?;
+                b.load(images)
+            }
+            Texture::Image { path, .. } => path.load(images),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Shapes of the `Noise` texture, each giving a number from 0.0 to 1.0 that is coloured by the ramp
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Pattern {
+    /// Soft clouds
+    #[default]
+    Perlin,
+    /// Billowy clouds with sharp creases, like smoke or fire
+    Turbulence,
+    /// Veins across the x axis, one per unit, bent by turbulence
+    Marble {
+        #[serde(default = "default_marble_distortion")]
+        distortion: f64,
+    },
+    /// Rings around the y axis, one per unit, made irregular by noise
+    Wood {
+        #[serde(default = "default_wood_distortion")]
+        distortion: f64,
+    },
+    /// Cells around randomly placed points, like cobblestones or scales
+    Voronoi {
+        /// How far the points stray from a regular grid, 0.0-1.0
+        #[serde(default = "default_jitter")]
+        jitter: f64,
+        #[serde(default)]
+        feature: Feature,
+    },
+}
+
+fn default_marble_distortion() -> f64 {
+    5.0
+}
+
+fn default_wood_distortion() -> f64 {
+    0.3
+}
+
+fn default_jitter() -> f64 {
+    1.0
+}
+
+/// What a `Voronoi` pattern shows
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Feature {
+    /// Distance to the closest point, dark in the middle of the cells
+    #[default]
+    Distance,
+    /// Dark lines along the borders between cells
+    Edges,
+    /// A different flat shade for every cell
+    Cells,
+}
+
+impl Pattern {
+    fn value(&self, p: &Vec3, octaves: u32, seed: u64) -> f64 {
+        match *self {
+            Pattern::Perlin => 0.5 * (fbm(p, octaves, seed) + 1.0),
+            Pattern::Turbulence => turbulence(p, octaves, seed),
+            Pattern::Marble { distortion } => {
+                0.5 * (1.0 + (TAU * p.x() + distortion * turbulence(p, octaves, seed)).sin())
+            }
+            Pattern::Wood { distortion } => {
+                let radius = (p.x() * p.x() + p.z() * p.z()).sqrt();
+                let rings = radius + distortion * fbm(p, octaves, seed);
+                rings - rings.floor()
+            }
+            Pattern::Voronoi { jitter, feature } => {
+                let cells = voronoi(p, jitter, seed);
+                match feature {
+                    Feature::Distance => cells.f1.min(1.0),
+                    Feature::Edges => (cells.f2 - cells.f1).min(1.0),
+                    Feature::Cells => cells.id,
+                }
+            }
+        }
+    }
+}
+
+/// Colours blended along 0.0-1.0, given as `(position, colour)` stops
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(from = "Vec<(f64, Color)>", into = "Vec<(f64, Color)>")]
+pub struct Ramp(Vec<(f64, Color)>);
+
+/// Black to white
+impl Default for Ramp {
+    fn default() -> Self {
+        Ramp(vec![(0.0, Color::new(0.0, 0.0, 0.0)), (1.0, Color::new(1.0, 1.0, 1.0))])
+    }
+}
+
+impl From<Vec<(f64, Color)>> for Ramp {
+    fn from(mut stops: Vec<(f64, Color)>) -> Self {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ramp(stops)
+    }
+}
+
+impl From<Ramp> for Vec<(f64, Color)> {
+    fn from(ramp: Ramp) -> Self {
+        ramp.0
+    }
+}
+
+impl Ramp {
+    /// Colour at `t`, holding the first and last colours beyond the ends
+    fn at(&self, t: f64) -> Color {
+        let next = self.0.partition_point(|&(position, _)| position <= t);
+
+        match (self.0.get(next.wrapping_sub(1)), self.0.get(next)) {
+            (Some(&(p0, c0)), Some(&(p1, c1))) => {
+                let f = (t - p0) / (p1 - p0);
+                (1.0 - f) * c0 + f * c1
+            }
+            (Some(&(_, c)), None) | (None, Some(&(_, c))) => c,
+            (None, None) => Color::default(),
+        }
+    }
+}
+
+/// What happens to texture coordinates outside of 0.0-1.0
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Wrap {
+    /// Tiles the image
+    #[default]
+    Repeat,
+    /// Stretches the edge pixels
+    Clamp,
+    /// Tiles the image, flipping every other copy so the edges line up
+    Mirror,
+}
+
+impl Wrap {
+    fn apply(self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+
+        (match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Clamp => i.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        }) as usize
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Filter {
+    /// Blocky, the pixel the point falls in
+    Nearest,
+    /// Smooth, blends the four closest pixels
+    #[default]
+    Bilinear,
+}
+
+/// Texture image that couldn't be loaded
+#[derive(Debug)]
+pub struct LoadError(pub PathBuf, pub Error);
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to load texture {}: {}", self.0.display(), self.1)
+    }
+}
+
+/// Images loaded while a config is processed, so that textures using the same file share one copy
+#[derive(Default)]
+pub struct Images {
+    loaded: RefCell<HashMap<PathBuf, Arc<Image>>>,
+}
+
+impl Images {
+    fn get(&self, path: &Path) -> Result<Arc<Image>, Error> {
+        let key = std::fs::canonicalize(path).map_err(Error::FailedToRead)?;
+        if let Some(image) = self.loaded.borrow().get(&key) {
+            return Ok(image.clone());
+        }
+
+        let image = Arc::new(read(path)?);
+        self.loaded.borrow_mut().insert(key, image.clone());
+
+        Ok(image)
+    }
+}
+
+fn read(path: &Path) -> Result<Image, Error> {
+    let image = Image::load(path)?;
+    if image.width == 0 || image.height == 0 {
+        return Err(Error::InvalidData("texture is empty"));
+    }
+
+    Ok(image)
+}
+
+/// Image file used by textures, loaded when the config is processed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(from = "PathBuf", into = "PathBuf")]
+pub struct ImageFile {
+    path: PathBuf,
+    /// `None` until the image is loaded
+    image: Option<Arc<Image>>,
+}
+
+impl From<PathBuf> for ImageFile {
+    fn from(path: PathBuf) -> Self {
+        ImageFile { path, image: None }
+    }
+}
+
+impl From<ImageFile> for PathBuf {
+    fn from(file: ImageFile) -> Self {
+        file.path
+    }
+}
+
+impl ImageFile {
+    /// Image file loaded right away, without sharing it with other textures
+    pub fn read(path: PathBuf) -> Result<ImageFile, Error> {
+        let image = read(&path)?;
+        Ok(ImageFile {
+            path,
+            image: Some(Arc::new(image)),
+        })
+    }
+
+    /// Image that isn't a file of its own, like one embedded in a model. The extension of `name`
+    /// tells the format, as for files
+    pub fn embedded(name: PathBuf, image: Image) -> Result<ImageFile, Error> {
+        if image.width == 0 || image.height == 0 {
+            return Err(Error::InvalidData("texture is empty"));
+        }
+
+        Ok(ImageFile {
+            path: name,
+            image: Some(Arc::new(image)),
+        })
+    }
+
+    fn load(&mut self, images: &Images) -> Result<(), LoadError> {
+        if self.image.is_none() {
+            let image = images.get(&self.path).map_err(|e| LoadError(self.path.clone(), e))?;
+            self.image = Some(image);
+        }
+
+        Ok(())
+    }
+
+    fn image(&self) -> &Image {
+        self.image.as_ref().expect("texture images are loaded when the config is processed")
+    }
+
+    /// 8 and 16-bit formats hold sRGB encoded colours, float formats are linear
+    fn is_srgb(&self) -> bool {
+        let extension = self.path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+        matches!(extension.to_ascii_lowercase().as_str(), "png" | "ppm" | "jpg" | "jpeg")
+    }
+
+    fn texel(&self, x: i64, y: i64, wrap: (Wrap, Wrap), srgb: bool) -> Color {
+        let image = self.image();
+        let c = image.get(wrap.0.apply(x, image.width), wrap.1.apply(y, image.height));
+
+        if srgb {
+            Color::new(srgb_eotf(c.r()), srgb_eotf(c.g()), srgb_eotf(c.b()))
+        } else {
+            c
+        }
+    }
+
+    /// Linear colour at (`u`, `v`), where (0, 0) is the bottom left corner of the image. `wrap` holds
+    /// the wrap along u and along v
+    fn lookup(&self, u: f64, v: f64, wrap: (Wrap, Wrap), filter: Filter, srgb: bool) -> Color {
+        // Pixel coordinates, image rows go from the top down
+        let x = u * self.image().width as f64;
+        let y = (1.0 - v) * self.image().height as f64;
+
+        match filter {
+            Filter::Nearest => self.texel(x.floor() as i64, y.floor() as i64, wrap, srgb),
+            Filter::Bilinear => {
+                // Pixel centres are at half coordinates
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let top = (1.0 - fx) * self.texel(x0, y0, wrap, srgb) + fx * self.texel(x0 + 1, y0, wrap, srgb);
+                let bottom =
+                    (1.0 - fx) * self.texel(x0, y0 + 1, wrap, srgb) + fx * self.texel(x0 + 1, y0 + 1, wrap, srgb);
+
+                (1.0 - fy) * top + fy * bottom
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solid_from_vec3() {
+        let texture: Texture = ron::from_str("Vec3(0.5, 0.25, 1.0)").unwrap();
+        assert_eq!(texture.value(0.3, 0.7, &Vec3::default()), Color::new(0.5, 0.25, 1.0));
+
+        let texture: Texture = ron::from_str("Solid(0.5, 0.25, 1.0)").unwrap();
+        assert_eq!(texture.value(0.0, 0.0, &Vec3::default()), Color::new(0.5, 0.25, 1.0));
+    }
+
+    #[test]
+    fn test_checker() {
+        let texture: Texture = ron::from_str("Checker(even: Vec3(1.0, 1.0, 1.0), odd: Uv, scale: 2.0)").unwrap();
+        assert_eq!(texture.value(0.1, 0.1, &Vec3::default()), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(texture.value(0.6, 0.1, &Vec3::default()), Color::new(0.6, 0.1, 0.0));
+        assert_eq!(texture.value(0.6, 0.6, &Vec3::default()), Color::new(1.0, 1.0, 1.0));
+        // Negative coordinates continue the pattern
+        assert_eq!(texture.value(-0.1, 0.1, &Vec3::default()), Color::new(-0.1, 0.1, 0.0));
+    }
+
+    fn file(width: usize, height: usize, pixels: Vec<Color>) -> ImageFile {
+        ImageFile {
+            path: PathBuf::new(),
+            image: Some(Arc::new(Image::new(width, height, pixels))),
+        }
+    }
+
+    #[test]
+    fn test_vertex_color() {
+        let material = crate::material::Material::default();
+        let corners = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+        let colors = [Color::new(1.0, 0.0, 0.0), Color::new(0.0, 1.0, 0.0), Color::new(0.0, 0.0, 1.0)];
+        let rec = crate::triangle::record(1.0, [0.5, 0.5, 0.0], &corners, None, &crate::triangle::DEFAULT_UVS, Some(&colors), &material);
+
+        // Also inside other textures, and white without vertex colours, leaving the other factor
+        let texture: Texture = ron::from_str("Multiply(VertexColor, Vec3(0.5, 1.0, 1.0))").unwrap();
+        assert_eq!(texture.value_at(&rec), Color::new(0.25, 0.5, 0.0));
+        assert_eq!(texture.value_at(&HitRecord { color: None, ..rec }), Color::new(0.5, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_wrap() {
+        let wrapped = |wrap: Wrap| (-3..7).map(|i| wrap.apply(i, 3)).collect::<Vec<_>>();
+
+        assert_eq!(wrapped(Wrap::Repeat), [0, 1, 2, 0, 1, 2, 0, 1, 2, 0]);
+        assert_eq!(wrapped(Wrap::Clamp), [0, 0, 0, 0, 1, 2, 2, 2, 2, 2]);
+        assert_eq!(wrapped(Wrap::Mirror), [2, 1, 0, 0, 1, 2, 2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_image_lookup() {
+        let pixels = vec![
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+            Color::new(1.0, 1.0, 1.0),
+        ];
+        let image = file(2, 2, pixels);
+        let nearest = |u, v, wrap| image.lookup(u, v, (wrap, wrap), Filter::Nearest, false);
+
+        // The top row of the image is at v = 1
+        assert_eq!(nearest(0.25, 0.75, Wrap::Repeat), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(nearest(0.75, 0.25, Wrap::Repeat), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(nearest(1.25, 1.75, Wrap::Repeat), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(nearest(1.25, 1.75, Wrap::Clamp), Color::new(0.0, 1.0, 0.0));
+        assert_eq!(nearest(1.25, 1.75, Wrap::Mirror), Color::new(1.0, 1.0, 1.0));
+
+        // Repeating along u while clamping along v
+        let mixed = image.lookup(1.25, 1.75, (Wrap::Repeat, Wrap::Clamp), Filter::Nearest, false);
+        assert_eq!(mixed, Color::new(1.0, 0.0, 0.0));
+        let mixed = image.lookup(1.25, -0.75, (Wrap::Repeat, Wrap::Clamp), Filter::Nearest, false);
+        assert_eq!(mixed, Color::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_bilinear() {
+        let image = file(2, 1, vec![Color::new(0.0, 0.0, 0.0), Color::new(1.0, 0.5, 0.25)]);
+        let bilinear = |u, wrap| image.lookup(u, 0.5, (wrap, wrap), Filter::Bilinear, false);
+
+        // Pixel centres give the pixel itself, halfway between them the average
+        assert_eq!(bilinear(0.25, Wrap::Clamp), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(bilinear(0.5, Wrap::Clamp), Color::new(0.5, 0.25, 0.125));
+        assert_eq!(bilinear(0.75, Wrap::Clamp), Color::new(1.0, 0.5, 0.25));
+        // Past the last centre, clamping holds the edge and repeating blends into the first pixel
+        assert_eq!(bilinear(0.95, Wrap::Clamp), Color::new(1.0, 0.5, 0.25));
+        assert!(bilinear(0.95, Wrap::Repeat).r() < 1.0);
+    }
+
+    #[test]
+    fn test_srgb() {
+        let image = file(1, 1, vec![Color::new(0.5, 1.0, 0.0)]);
+
+        let linear = image.lookup(0.5, 0.5, (Wrap::Repeat, Wrap::Repeat), Filter::Bilinear, true);
+        assert!((linear.r() - 0.2140411405).abs() < 1e-6);
+        assert_eq!((linear.g(), linear.b()), (1.0, 0.0));
+    }
+
+    #[test]
+    fn test_image_options() {
+        let path = std::env::temp_dir().join(format!("rt_texture_{}.ppm", std::process::id()));
+        std::fs::write(&path, b"P3 2 1 255\n255 255 255 0 0 0\n").unwrap();
+
+        let config = format!(
+            "[Image(path: {path:?}, filter: Nearest, scale: (2.0, 1.0), offset: (0.5, 0.0)), Image(path: {path:?})]"
+        );
+        let mut textures: Vec<Texture> = ron::from_str(&config).unwrap();
+        let images = Images::default();
+        for texture in &mut textures {
+            texture.load(&images).unwrap();
+        }
+
+        // Files written again are loaded again for the next config
+        std::fs::write(&path, b"P3 1 1 255\n0 0 0\n").unwrap();
+        let mut rewritten: Texture = ron::from_str(&format!("Image(path: {path:?})")).unwrap();
+        rewritten.load(&Images::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let Texture::Image { path: c, .. } = &rewritten else { unreachable!() };
+        assert_eq!(c.image().width, 1);
+
+        let [Texture::Image { path: a, .. }, Texture::Image { path: b, .. }] = &textures[..] else {
+            panic!("{textures:?}");
+        };
+        // Both textures share the loaded image
+        assert!(Arc::ptr_eq(a.image.as_ref().unwrap(), b.image.as_ref().unwrap()));
+
+        // u = 0.1 maps to 0.7 in the image, in the black pixel
+        assert_eq!(textures[0].value(0.1, 0.5, &Vec3::default()), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(textures[0].value(0.3, 0.5, &Vec3::default()), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_ramp() {
+        let ramp: Ramp = ron::from_str("[(1.0, Vec3(0.0, 0.0, 1.0)), (0.0, Vec3(1.0, 0.0, 0.0)), (0.5, Vec3(0.0, 1.0, 0.0))]").unwrap();
+
+        assert_eq!(ramp.at(-1.0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(ramp.at(0.25), Color::new(0.5, 0.5, 0.0));
+        assert_eq!(ramp.at(0.5), Color::new(0.0, 1.0, 0.0));
+        assert_eq!(ramp.at(2.0), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(Ramp(vec![]).at(0.5), Color::default());
+        assert_eq!(Ramp::default().at(0.5), Color::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_noise() {
+        let textures: Vec<Texture> = ron::from_str(
+            "[
+                Noise(),
+                Noise(pattern: Turbulence, scale: 4.0, octaves: 7, seed: 3),
+                Noise(pattern: Marble(), ramp: [(0.0, Vec3(0.2, 0.2, 0.3)), (1.0, Vec3(0.9, 0.9, 0.8))]),
+                Noise(pattern: Wood(distortion: 0.1)),
+                Noise(pattern: Voronoi(feature: Edges)),
+            ]",
+        )
+        .unwrap();
+
+        for texture in &textures {
+            let mut grey = true;
+            for i in 0..200 {
+                let p = Vec3::new(i as f64 * 0.173, (i as f64 * 0.05).sin(), -1.3);
+                let c = texture.value(0.0, 0.0, &p);
+
+                // Looking up the same point again gives the same colour, whatever the surface coordinates
+                assert_eq!(texture.value(0.7, 0.2, &p), c);
+                assert!((0.0..=1.0).contains(&c.r()) && (0.0..=1.0).contains(&c.b()));
+                grey &= c.r() == c.g() && c.g() == c.b();
+            }
+            // Only the marble has a coloured ramp
+            assert_eq!(grey, !matches!(texture, Texture::Noise { pattern: Pattern::Marble { .. }, .. }));
+        }
+
+        let Texture::Noise { pattern, .. } = &textures[2] else { unreachable!() };
+        assert_eq!(*pattern, Pattern::Marble { distortion: 5.0 });
+
+        // The seed picks a different pattern
+        let p = Vec3::new(0.3, 0.6, 0.2);
+        let seeded = |seed| Texture::Noise {
+            pattern: Pattern::Perlin,
+            scale: 1.0,
+            octaves: 4,
+            ramp: Ramp::default(),
+            seed,
+        };
+        assert_ne!(seeded(0).value(0.0, 0.0, &p), seeded(1).value(0.0, 0.0, &p));
+    }
+}
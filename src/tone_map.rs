@@ -0,0 +1,191 @@
+use crate::color::Color;
+use serde::{Deserialize, Serialize};
+
+/// Operator squeezing the unbounded scene radiance into the 0.0-1.0 range of a display
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum ToneMap {
+    /// Cuts off everything above 1.0
+    #[default]
+    Clamp,
+    Reinhard,
+    /// Reinhard that maps `white` (and everything brighter) to full white
+    ExtendedReinhard { white: f64 },
+    /// Fit of the ACES filmic reference rendering and output transforms
+    Aces,
+    /// AgX-style filmic curve, desaturates very bright colours towards white
+    Agx,
+}
+
+fn luminance(c: Color) -> f64 {
+    0.2126 * c.r() + 0.7152 * c.g() + 0.0722 * c.b()
+}
+
+fn mat_mul(m: &[[f64; 3]; 3], c: Color) -> Color {
+    Color::new(
+        m[0][0] * c.r() + m[0][1] * c.g() + m[0][2] * c.b(),
+        m[1][0] * c.r() + m[1][1] * c.g() + m[1][2] * c.b(),
+        m[2][0] * c.r() + m[2][1] * c.g() + m[2][2] * c.b(),
+    )
+}
+
+fn map_channels(c: Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(c.r()), f(c.g()), f(c.b()))
+}
+
+fn clamp(c: Color) -> Color {
+    map_channels(c, |x| x.clamp(0.0, 1.0))
+}
+
+/// Scales the colour so that its luminance follows the extended Reinhard curve
+fn reinhard(c: Color, white: f64) -> Color {
+    let l = luminance(c);
+    if l <= 0.0 {
+        return Color::default();
+    }
+
+    let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+    c * (mapped / l)
+}
+
+// Stephen Hill's fit of the ACES RRT + ODT, working in the ACES input space
+const ACES_INPUT: [[f64; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+const ACES_OUTPUT: [[f64; 3]; 3] = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn aces(c: Color) -> Color {
+    let fitted = map_channels(mat_mul(&ACES_INPUT, c), |v| {
+        (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.432951) + 0.238081)
+    });
+
+    mat_mul(&ACES_OUTPUT, fitted)
+}
+
+// Inset and outset matrices and contrast curve of the minimal AgX implementation
+const AGX_INSET: [[f64; 3]; 3] = [
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+];
+const AGX_OUTSET: [[f64; 3]; 3] = [
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+];
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+
+fn agx(c: Color) -> Color {
+    let encoded = map_channels(mat_mul(&AGX_INSET, c), |v| {
+        let x = (v.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV) - AGX_MIN_EV)
+            / (AGX_MAX_EV - AGX_MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+
+    // The curve outputs display encoded values, decode them back to linear for the sRGB transfer function
+    map_channels(mat_mul(&AGX_OUTSET, encoded), |v| v.max(0.0).powf(2.2))
+}
+
+impl ToneMap {
+    /// Maps linear scene radiance to linear display values in 0.0-1.0
+    pub fn apply(&self, c: Color) -> Color {
+        let mapped = match *self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => reinhard(c, f64::INFINITY),
+            ToneMap::ExtendedReinhard { white } => reinhard(c, white),
+            ToneMap::Aces => aces(c),
+            ToneMap::Agx => agx(c),
+        };
+
+        clamp(mapped)
+    }
+}
+
+/// sRGB transfer function, encodes a linear 0.0-1.0 value for display
+pub fn srgb_oetf(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse of `srgb_oetf`, decodes an sRGB encoded 0.0-1.0 value back to linear
+pub fn srgb_eotf(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    const OPERATORS: [ToneMap; 5] = [
+        ToneMap::Clamp,
+        ToneMap::Reinhard,
+        ToneMap::ExtendedReinhard { white: 4.0 },
+        ToneMap::Aces,
+        ToneMap::Agx,
+    ];
+
+    #[test]
+    fn test_srgb_oetf() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert_approx_eq!(srgb_oetf(1.0), 1.0);
+        assert_approx_eq!(srgb_oetf(0.18), 0.4613561295, 1e-6);
+    }
+
+    #[test]
+    fn test_srgb_eotf() {
+        for x in [0.0, 0.002, 0.04045, 0.2, 0.5, 1.0] {
+            assert_approx_eq!(srgb_eotf(srgb_oetf(x)), x);
+        }
+        assert_approx_eq!(srgb_eotf(0.5), 0.2140411405, 1e-6);
+    }
+
+    #[test]
+    fn test_output_in_display_range() {
+        for op in OPERATORS {
+            for v in [0.0, 0.01, 0.18, 1.0, 5.0, 1000.0] {
+                let c = op.apply(Color::new(v, 0.5 * v, 0.1 * v));
+                for channel in [c.r(), c.g(), c.b()] {
+                    assert!((0.0..=1.0).contains(&channel), "{op:?} gave {c:?} for {v}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_monotonic() {
+        for op in OPERATORS {
+            let mut previous = -1.0;
+            for i in 0..100 {
+                let v = luminance(op.apply(Color::new(1.0, 1.0, 1.0) * (i as f64 * 0.1)));
+                assert!(v >= previous - 1e-9, "{op:?} is not monotonic");
+                previous = v;
+            }
+        }
+    }
+
+    #[test]
+    fn test_reinhard() {
+        let grey = Color::new(1.0, 1.0, 1.0);
+        assert_approx_eq!(ToneMap::Reinhard.apply(grey).r(), 0.5);
+        assert_approx_eq!(ToneMap::ExtendedReinhard { white: 4.0 }.apply(4.0 * grey).g(), 1.0);
+        assert_eq!(ToneMap::Clamp.apply(Color::new(2.0, 0.5, -1.0)), Color::new(1.0, 0.5, 0.0));
+    }
+}
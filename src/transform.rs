@@ -0,0 +1,290 @@
+use std::ops::Mul;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vec3::Vec3;
+
+/// Row-major 4x4 matrix for affine transforms of points and directions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4(pub [[f64; 4]; 4]);
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        let mut m = Mat4::IDENTITY;
+        for axis in 0..3 {
+            m.0[axis][3] = offset[axis];
+        }
+        m
+    }
+
+    pub fn scaling(factor: Vec3) -> Mat4 {
+        let mut m = Mat4::IDENTITY;
+        for axis in 0..3 {
+            m.0[axis][axis] = factor[axis];
+        }
+        m
+    }
+
+    /// Counterclockwise rotation by `angle` radians, looking down `axis` (0 for x, 1 for y, 2 for z)
+    pub fn rotation(axis: usize, angle: f64) -> Mat4 {
+        let (sin, cos) = angle.sin_cos();
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut m = Mat4::IDENTITY;
+        m.0[a][a] = cos;
+        m.0[a][b] = -sin;
+        m.0[b][a] = sin;
+        m.0[b][b] = cos;
+        m
+    }
+
+    /// Rotation by the quaternion x i + y j + z k + w, which is normalised first
+    pub fn from_quaternion(x: f64, y: f64, z: f64, w: f64) -> Mat4 {
+        let length = (x * x + y * y + z * z + w * w).sqrt();
+        if length == 0.0 {
+            return Mat4::IDENTITY;
+        }
+        let (x, y, z, w) = (x / length, y / length, z / length, w / length);
+
+        Mat4([
+            [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w), 2.0 * (x * z + y * w), 0.0],
+            [2.0 * (x * y + z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w), 0.0],
+            [2.0 * (x * z - y * w), 2.0 * (y * z + x * w), 1.0 - 2.0 * (x * x + y * y), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Counterclockwise rotation by `angle` radians around `axis`, looking down the axis
+    pub fn axis_angle(axis: Vec3, angle: f64) -> Mat4 {
+        if axis.length() == 0.0 {
+            return Mat4::IDENTITY;
+        }
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        let v = sin * axis.unit();
+
+        Mat4::from_quaternion(v.x(), v.y(), v.z(), cos)
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = Mat4::IDENTITY;
+        for (i, row) in m.0.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.0[j][i];
+            }
+        }
+        m
+    }
+
+    /// Determinant of the upper 3x3 part, negative when the transform mirrors
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Inverse by Gauss-Jordan elimination, `None` when the matrix squashes space flat
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut m = self.0;
+        let mut inverse = Mat4::IDENTITY.0;
+
+        for column in 0..4 {
+            let pivot = (column..4).max_by(|&a, &b| m[a][column].abs().total_cmp(&m[b][column].abs()))?;
+            if m[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            m.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1.0 / m[column][column];
+            for j in 0..4 {
+                m[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+
+            for row in 0..4 {
+                let factor = m[row][column];
+                if row != column && factor != 0.0 {
+                    for j in 0..4 {
+                        m[row][j] -= factor * m[column][j];
+                        inverse[row][j] -= factor * inverse[column][j];
+                    }
+                }
+            }
+        }
+
+        Some(Mat4(inverse))
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        self.transform_vector(p) + Vec3::new(self.0[0][3], self.0[1][3], self.0[2][3])
+    }
+
+    /// Transforms a direction, which isn't affected by the translation
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.0[i][k] * other.0[k][j]).sum();
+            }
+        }
+        Mat4(m)
+    }
+}
+
+/// Rotation of an object around the origin
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Rotation {
+    /// Degrees around the x, y and z axes, applied in that order. Can also be written as a `Vec3`
+    #[serde(alias = "Vec3")]
+    Euler(f64, f64, f64),
+    /// Degrees counterclockwise around `axis`, looking down the axis
+    AxisAngle { axis: Vec3, angle: f64 },
+    /// Quaternion x i + y j + z k + w, which is normalised first
+    Quaternion(f64, f64, f64, f64),
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation::Euler(0.0, 0.0, 0.0)
+    }
+}
+
+impl Rotation {
+    pub fn matrix(&self) -> Mat4 {
+        match *self {
+            Rotation::Euler(x, y, z) => [x, y, z]
+                .iter()
+                .enumerate()
+                .fold(Mat4::IDENTITY, |m, (axis, angle)| Mat4::rotation(axis, angle.to_radians()) * m),
+            Rotation::AxisAngle { axis, angle } => Mat4::axis_angle(axis, angle.to_radians()),
+            Rotation::Quaternion(x, y, z, w) => Mat4::from_quaternion(x, y, z, w),
+        }
+    }
+}
+
+/// Placement of an object, applied as scale, then rotation, then translation
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Transform {
+    #[serde(default)]
+    pub translate: Vec3,
+    #[serde(default)]
+    pub rotate: Rotation,
+    #[serde(default = "default_scale")]
+    pub scale: Vec3,
+}
+
+fn default_scale() -> Vec3 {
+    Vec3::new(1.0, 1.0, 1.0)
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translate: Vec3::default(),
+            rotate: Rotation::default(),
+            scale: default_scale(),
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::translation(self.translate) * self.rotate.matrix() * Mat4::scaling(self.scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn test_rotation() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        let quarter = std::f64::consts::FRAC_PI_2;
+
+        assert!(close(Mat4::rotation(0, quarter).transform_vector(&y), z));
+        assert!(close(Mat4::rotation(1, quarter).transform_vector(&z), x));
+        assert!(close(Mat4::rotation(2, quarter).transform_vector(&x), y));
+
+        // Half of the angle goes into the quaternion, and its length doesn't matter
+        let (sin, cos) = (quarter / 2.0).sin_cos();
+        let quaternion = Mat4::from_quaternion(0.0, 2.0 * sin, 0.0, 2.0 * cos);
+        assert!(close(quaternion.transform_vector(&z), x));
+        assert!(close(quaternion.transform_vector(&y), y));
+
+        let axis_angle = Mat4::axis_angle(Vec3::new(1.0, 1.0, 1.0), 120f64.to_radians());
+        assert!(close(axis_angle.transform_vector(&x), y));
+        assert!(close(axis_angle.transform_vector(&y), z));
+    }
+
+    #[test]
+    fn test_rotation_syntax() {
+        let parse = |source: &str| ron::from_str::<Rotation>(source).unwrap().matrix().transform_vector(&Vec3::new(1.0, 0.0, 0.0));
+        let y = Vec3::new(0.0, 1.0, 0.0);
+
+        assert!(close(parse("Vec3(0.0, 0.0, 90.0)"), y));
+        assert!(close(parse("Euler(0.0, 0.0, 90.0)"), y));
+        assert!(close(parse("AxisAngle(axis: Vec3(0.0, 0.0, 2.0), angle: 90.0)"), y));
+        assert!(close(parse("Quaternion(0.0, 0.0, 0.7071068, 0.7071068)"), y));
+    }
+
+    #[test]
+    fn test_transform() {
+        let transform = Transform {
+            translate: Vec3::new(1.0, 2.0, 3.0),
+            rotate: Rotation::Euler(90.0, 0.0, 90.0),
+            scale: Vec3::new(2.0, 1.0, 1.0),
+        };
+        let m = transform.matrix();
+
+        // x is scaled, then turned by z onto y; y is turned by x onto z and stays
+        assert!(close(m.transform_point(&Vec3::new(1.0, 0.0, 0.0)), Vec3::new(1.0, 4.0, 3.0)));
+        assert!(close(m.transform_vector(&Vec3::new(0.0, 1.0, 0.0)), Vec3::new(0.0, 0.0, 1.0)));
+        assert!((m.determinant3() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_inverse() {
+        let m = Transform {
+            translate: Vec3::new(-1.0, 0.5, 4.0),
+            rotate: Rotation::Euler(30.0, -45.0, 10.0),
+            scale: Vec3::new(0.5, 2.0, 3.0),
+        }
+        .matrix();
+        let inverse = m.inverse().unwrap();
+        let product = m * inverse;
+
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((product.0[i][j] - Mat4::IDENTITY.0[i][j]).abs() < 1e-9);
+            }
+        }
+        assert_eq!(Mat4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse(), None);
+        assert_eq!(m.transpose().transpose(), m);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::aabb::Aabb;
+use crate::color::Color;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampling::Onb;
+use crate::vec3::Vec3;
+
+/// Texture coordinates given to the corners of triangles without any
+pub const DEFAULT_UVS: [(f64, f64); 3] = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)];
+
+#[derive(Debug)]
+pub struct Triangle {
+    vertices: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: [(f64, f64); 3],
+    material: Material,
+}
+
+impl Triangle {
+    /// The front is the side from which the vertices go around counterclockwise. `normals` are
+    /// interpolated over the triangle for smooth shading
+    pub fn new(vertices: [Vec3; 3], normals: Option<[Vec3; 3]>, uvs: [(f64, f64); 3], material: Material) -> Triangle {
+        Triangle {
+            vertices,
+            normals,
+            uvs,
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, barycentric) = intersect(r, &self.vertices, t_min, t_max)?;
+
+        Some(record(t, barycentric, &self.vertices, self.normals.as_ref(), &self.uvs, None, &self.material))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        bounds(&self.vertices)
+    }
+}
+
+/// Box around a triangle, slightly padded so that triangles lying in an axis plane still have a
+/// volume for rays to pass through
+pub fn bounds(p: &[Vec3; 3]) -> Aabb {
+    let aabb = Aabb::new(p[0], p[1]).grow(&p[2]);
+    let size = [aabb.min, aabb.max]
+        .iter()
+        .map(|v| v.x().abs().max(v.y().abs()).max(v.z().abs()))
+        .fold(1.0, f64::max);
+    let pad = Vec3::new(1.0, 1.0, 1.0) * (1e-9 * size);
+
+    Aabb::new(aabb.min - pad, aabb.max + pad)
+}
+
+/// Watertight ray/triangle intersection (Woop, Benthin and Wald 2013). Rays through an edge or a vertex
+/// shared by several triangles always hit at least one of them, so meshes don't leak light through
+/// their seams. Returns the distance along the ray and the barycentric weights of the vertices
+pub fn intersect(r: &Ray, p: &[Vec3; 3], t_min: f64, t_max: f64) -> Option<(f64, [f64; 3])> {
+    let d = r.direction;
+
+    // Work in a space where the ray goes along +z from the origin
+    let kz = (0..3).fold(0, |best, axis| if d[axis].abs() > d[best].abs() { axis } else { best });
+    let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+    if d[kz] < 0.0 {
+        // Keeps the winding of the triangle when the ray is flipped
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    let (sx, sy, sz) = (d[kx] / d[kz], d[ky] / d[kz], 1.0 / d[kz]);
+    let [a, b, c] = p.map(|v| v - r.origin);
+    let shear = |v: Vec3| (v[kx] - sx * v[kz], v[ky] - sy * v[kz]);
+    let ((ax, ay), (bx, by), (cx, cy)) = (shear(a), shear(b), shear(c));
+
+    // Scaled barycentric coordinates, all of the same sign inside the triangle
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / det;
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+
+    Some((t, [u / det, v / det, w / det]))
+}
+
+/// Hit record at the given barycentric weights of a triangle
+pub fn record<'a>(
+    t: f64,
+    barycentric: [f64; 3],
+    p: &[Vec3; 3],
+    normals: Option<&[Vec3; 3]>,
+    uvs: &[(f64, f64); 3],
+    colors: Option<&[Color; 3]>,
+    material: &'a Material,
+) -> HitRecord<'a> {
+    let [b0, b1, b2] = barycentric;
+    let point = b0 * p[0] + b1 * p[1] + b2 * p[2];
+    let mut geometric_normal = Vec3::unit_vector(&Vec3::cross(&(p[1] - p[0]), &(p[2] - p[0])));
+
+    let normal = match normals.map(|n| b0 * n[0] + b1 * n[1] + b2 * n[2]) {
+        Some(n) if n.length() > 1e-12 => {
+            // The vertex normals decide which side is the outside
+            if Vec3::dot(&n, &geometric_normal) < 0.0 {
+                geometric_normal = -geometric_normal;
+            }
+            Vec3::unit_vector(&n)
+        }
+        _ => geometric_normal,
+    };
+
+    let (u, v) = (
+        b0 * uvs[0].0 + b1 * uvs[1].0 + b2 * uvs[2].0,
+        b0 * uvs[0].1 + b1 * uvs[1].1 + b2 * uvs[2].1,
+    );
+
+    // Solve the edges for the change along u and v
+    let (du02, dv02) = (uvs[0].0 - uvs[2].0, uvs[0].1 - uvs[2].1);
+    let (du12, dv12) = (uvs[1].0 - uvs[2].0, uvs[1].1 - uvs[2].1);
+    let (dp02, dp12) = (p[0] - p[2], p[1] - p[2]);
+    let determinant = du02 * dv12 - dv02 * du12;
+
+    let (dpdu, dpdv) = if determinant.abs() > 1e-12 {
+        (
+            (dv12 * dp02 - dv02 * dp12) / determinant,
+            (du02 * dp12 - du12 * dp02) / determinant,
+        )
+    } else {
+        let frame = Onb::from_w(&geometric_normal);
+        (frame.u, frame.v)
+    };
+
+    HitRecord {
+        t,
+        point,
+        normal,
+        geometric_normal,
+        u,
+        v,
+        dpdu,
+        dpdv,
+        color: colors.map(|c| b0 * c[0] + b1 * c[1] + b2 * c[2]),
+        material,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    fn triangle() -> Triangle {
+        Triangle::new(
+            [Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0)],
+            None,
+            DEFAULT_UVS,
+            Material::default(),
+        )
+    }
+
+    #[test]
+    fn test_hit() {
+        let triangle = triangle();
+        let rec = triangle
+            .hit(&Ray::new(Vec3::new(0.5, 0.25, 3.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::MAX)
+            .unwrap();
+
+        assert_eq!(rec.t, 3.0);
+        assert_eq!(rec.point, Vec3::new(0.5, 0.25, 0.0));
+        assert_eq!(rec.geometric_normal, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!((rec.u, rec.v), (0.25, 0.125));
+        assert_eq!((rec.dpdu, rec.dpdv), (Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0)));
+
+        // Both sides can be hit, but only within the triangle and the range of the ray
+        assert!(triangle
+            .hit(&Ray::new(Vec3::new(0.5, 0.25, -3.0), Vec3::new(0.0, 0.0, 1.0)), 0.0, f64::MAX)
+            .is_some());
+        assert!(triangle
+            .hit(&Ray::new(Vec3::new(1.5, 1.5, 3.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::MAX)
+            .is_none());
+        assert!(triangle
+            .hit(&Ray::new(Vec3::new(0.5, 0.25, 3.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, 2.0)
+            .is_none());
+        assert!(triangle
+            .hit(&Ray::new(Vec3::new(0.5, 0.25, 3.0), Vec3::new(1.0, 0.0, 0.0)), 0.0, f64::MAX)
+            .is_none());
+    }
+
+    #[test]
+    fn test_interpolated_normals() {
+        let triangle = Triangle::new(
+            [Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0)],
+            Some([Vec3::new(0.0, 0.0, -1.0), Vec3::new(1.0, 0.0, -1.0), Vec3::new(0.0, 0.0, -1.0)]),
+            DEFAULT_UVS,
+            Material::default(),
+        );
+        let rec = triangle
+            .hit(&Ray::new(Vec3::new(1.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::MAX)
+            .unwrap();
+
+        // Halfway along the first edge, and the vertex normals turn the triangle around
+        assert!((rec.normal - Vec3::unit_vector(&Vec3::new(0.5, 0.0, -1.0))).length() < 1e-9);
+        assert_eq!(rec.geometric_normal, Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn test_watertight() {
+        // A fan of triangles around a vertex, which share that vertex and the edges to the corners
+        let corners = [
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.3),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(-1.0, 1.0, -0.2),
+        ];
+        let centre = Vec3::new(0.1, -0.05, 0.05);
+        let fan: Vec<[Vec3; 3]> = (0..4).map(|i| [centre, corners[i], corners[(i + 1) % 4]]).collect();
+        let mut rng = StdRng::seed_from_u64(3);
+
+        for _ in 0..20_000 {
+            // Aim at the shared vertex and edges from all sorts of directions
+            let target = if rng.gen_bool(0.2) {
+                centre
+            } else {
+                centre + rng.gen::<f64>() * (corners[rng.gen_range(0..4)] - centre)
+            };
+            let origin = target
+                + Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(0.2..1.0)) * 1e3;
+
+            let ray = Ray::new(origin, target - origin);
+            let hits = fan.iter().filter(|p| intersect(&ray, p, 0.0, f64::MAX).is_some()).count();
+            assert!(hits >= 1, "{ray:?} slipped through");
+        }
+    }
+
+    #[test]
+    fn test_bounds() {
+        let aabb = triangle().bounding_box();
+
+        assert!(aabb.min.z() < 0.0 && aabb.max.z() > 0.0);
+        assert!(aabb.hit(&Ray::new(Vec3::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0)), 0.0, f64::MAX));
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     iter::Sum,
-    ops::{Add, AddAssign, Div, DivAssign, Mul, Neg, Sub},
+    ops::{Add, AddAssign, Div, DivAssign, Index, Mul, Neg, Sub},
 };
 
 use rand::{distributions::Standard, prelude::*};
@@ -85,6 +85,14 @@ impl Vec3 {
         )
     }
 
+    pub fn min(v1: &Vec3, v2: &Vec3) -> Vec3 {
+        Vec3(v1.0.min(v2.0), v1.1.min(v2.1), v1.2.min(v2.2))
+    }
+
+    pub fn max(v1: &Vec3, v2: &Vec3) -> Vec3 {
+        Vec3(v1.0.max(v2.0), v1.1.max(v2.1), v1.2.max(v2.2))
+    }
+
     pub fn random_init(min: f64, max: f64) -> Vec3 {
         let mut rng = rand::thread_rng();
         Vec3(
@@ -99,6 +107,19 @@ impl Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &Self::Output {
+        match axis {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("Vec3 axis out of range: {axis}"),
+        }
+    }
+}
+
 impl Neg for Vec3 {
     type Output = Self;
 
@@ -200,6 +221,20 @@ mod tests {
         assert_eq!(Vec3::new(10.0, 8.0, 2.0) / 2.0, Vec3::new(5.0, 4.0, 1.0));
     }
 
+    #[test]
+    fn test_vec3_min_max() {
+        let a = Vec3::new(1.0, 5.0, -3.0);
+        let b = Vec3::new(2.0, -1.0, -4.0);
+        assert_eq!(Vec3::min(&a, &b), Vec3::new(1.0, -1.0, -4.0));
+        assert_eq!(Vec3::max(&a, &b), Vec3::new(2.0, 5.0, -3.0));
+    }
+
+    #[test]
+    fn test_vec3_index() {
+        let v = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!((v[0], v[1], v[2]), (1.0, 2.0, 3.0));
+    }
+
     #[test]
     fn test_vec3_negate() {
         assert_eq!(-Vec3::new(1.0, 2.0, 3.0), Vec3::new(-1.0, -2.0, -3.0));
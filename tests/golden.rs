@@ -0,0 +1,122 @@
+//! Renders the scenes in `tests/scenes` and compares them against the reference images in `tests/golden`.
+//!
+//! After an intended change to the rendered images, regenerate the references with
+//! `UPDATE_GOLDEN=1 cargo test --test golden` and check the new images before committing them.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const MAX_RMSE: f64 = 2.0; // Root mean square error allowed, in 8-bit steps
+
+struct Image {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+fn read_png(path: &Path) -> Image {
+    let file = File::open(path).unwrap_or_else(|e| panic!("Failed to open {}: {e}", path.display()));
+    let mut reader = png::Decoder::new(file).read_info().expect("Failed to decode PNG");
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).expect("Failed to decode PNG");
+    data.truncate(info.buffer_size());
+
+    assert_eq!(info.color_type, png::ColorType::Rgb, "{}", path.display());
+
+    Image {
+        width: info.width,
+        height: info.height,
+        data,
+    }
+}
+
+fn rmse(a: &Image, b: &Image) -> f64 {
+    let sum: f64 = a
+        .data
+        .iter()
+        .zip(&b.data)
+        .map(|(&x, &y)| (x as f64 - y as f64).powi(2))
+        .sum();
+
+    (sum / a.data.len() as f64).sqrt()
+}
+
+fn render(scene: &str, output: &Path) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let status = Command::new(env!("CARGO_BIN_EXE_rt"))
+        .current_dir(root)
+        .arg("--config")
+        .arg(root.join("tests/scenes").join(format!("{scene}.ron")))
+        .arg("--output")
+        .arg(output)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .expect("Failed to run the renderer");
+
+    assert!(status.success(), "Rendering {scene} failed");
+}
+
+fn check(scene: &str) {
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{scene}.png"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        render(scene, &golden);
+        return;
+    }
+
+    let actual_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{scene}.png"));
+    render(scene, &actual_path);
+
+    let expected = read_png(&golden);
+    let actual = read_png(&actual_path);
+
+    assert_eq!(
+        (actual.width, actual.height),
+        (expected.width, expected.height),
+        "{scene} has a different resolution than its reference"
+    );
+
+    let error = rmse(&expected, &actual);
+    assert!(
+        error <= MAX_RMSE,
+        "{scene} differs from its reference (RMSE {error:.3} > {MAX_RMSE}), see {}",
+        actual_path.display()
+    );
+}
+
+macro_rules! golden_tests {
+    ($($scene:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $scene() {
+                check(stringify!($scene));
+            }
+        )*
+    };
+}
+
+golden_tests!(
+    sphere,
+    cube,
+    cylinder,
+    plane,
+    metal,
+    microfacet,
+    principled,
+    textures,
+    noise,
+    bump,
+    mesh,
+    obj,
+    scans,
+    instances,
+    groups,
+    csg,
+    gltf,
+    dielectric,
+    diffuse_light,
+    environment,
+);